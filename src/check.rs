@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{command::CompileCommand, config::Check};
+
+/// Result of checking a database against the filesystem.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub entries: usize,
+    pub missing_directories: usize,
+    pub missing_files: usize,
+    pub missing_includes: usize,
+    /// One line per problem, naming the entry.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+/// Include directories of an entry, as written.
+fn include_dirs(command: &CompileCommand) -> Vec<&str> {
+    let mut dirs = Vec::new();
+    let mut iter = command.arguments.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-I" | "-isystem" | "-iquote" | "-idirafter" => dirs.extend(iter.next().map(|s| s.as_str())),
+            a if a.starts_with("-I") => dirs.push(&a[2..]),
+            _ => {}
+        }
+    }
+    dirs
+}
+
+/// Checks that the directory, the file and the include directories of every
+/// entry exist.
+pub fn check(entries: &[CompileCommand]) -> Report {
+    let mut report = Report { entries: entries.len(), ..Default::default() };
+    for entry in entries {
+        let directory = Path::new(&entry.directory);
+        if !directory.is_dir() {
+            report.missing_directories += 1;
+            report.warnings.push(format!("{}: directory {} doesn't exist", entry.file, entry.directory));
+        }
+        if !directory.join(&entry.file).is_file() {
+            report.missing_files += 1;
+            report.warnings.push(format!("{}: file doesn't exist in {}", entry.file, entry.directory));
+        }
+        for dir in include_dirs(entry) {
+            if !directory.join(dir).is_dir() {
+                report.missing_includes += 1;
+                report.warnings.push(format!("{}: include directory {} doesn't exist", entry.file, dir));
+            }
+        }
+    }
+    report
+}
+
+impl Report {
+    pub fn summary(&self) -> String {
+        format!(
+            "checked {} entries: {} missing directories, {} missing files, {} missing include directories",
+            self.entries, self.missing_directories, self.missing_files, self.missing_includes
+        )
+    }
+
+    /// Whether there are more problems than `limits` allow.
+    pub fn exceeds(&self, limits: &Check) -> bool {
+        self.missing_directories > limits.max_missing_directories
+            || self.missing_files > limits.max_missing_files
+            || limits.max_missing_includes.is_some_and(|m| self.missing_includes > m)
+    }
+
+    /// Prints the warnings and the summary on stderr.
+    pub fn print(&self) {
+        for warning in &self.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        eprintln!("ccjson: {}", self.summary());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let root = env!("CARGO_MANIFEST_DIR");
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect();
+        let entries = [
+            CompileCommand::new(root, "src/lib.rs", args(&["gcc", "-Isrc", "-I", "missing"])),
+            CompileCommand::new(root, "src/missing.c", args(&["gcc", "-isystem", "/usr"])),
+            CompileCommand::new("/missing", "a.c", args(&["gcc"])),
+        ];
+        let report = check(&entries);
+        assert_eq!((report.missing_directories, report.missing_files, report.missing_includes), (1, 2, 1));
+        assert_eq!(report.warnings.len(), 4);
+
+        let mut limits = Check { max_missing_directories: 1, max_missing_files: 2, ..Default::default() };
+        assert!(!report.exceeds(&limits));
+        limits.max_missing_includes = Some(0);
+        assert!(report.exceeds(&limits));
+    }
+}
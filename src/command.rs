@@ -0,0 +1,269 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use serde_json::{Map, Value};
+
+use crate::parser::Parser;
+
+/// A single entry of the compilation database.
+///
+/// `arguments` holds the compiler and its flags, the source file is kept
+/// apart in `file` and appended when the entry is written out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileCommand {
+    pub directory: String,
+    pub file: String,
+    pub arguments: Vec<String>,
+}
+
+impl CompileCommand {
+    pub fn new(directory: &str, file: &str, arguments: Vec<String>) -> CompileCommand {
+        CompileCommand {
+            directory: directory.to_string(),
+            file: file.to_string(),
+            arguments,
+        }
+    }
+
+    /// Reads an entry of a compilation database, in either the `arguments`
+    /// or the `command` form.
+    pub fn from_json(value: &Value) -> Result<CompileCommand, String> {
+        let field = |key: &str| value.get(key).and_then(|v| v.as_str());
+        let directory = field("directory").ok_or("entry without directory")?;
+        let file = field("file").ok_or("entry without file")?;
+        let mut arguments: Vec<String> = match (value.get("arguments"), field("command")) {
+            (Some(Value::Array(args)), _) => args.iter()
+                .map(|a| a.as_str().map(|s| s.to_string()).ok_or("non string argument"))
+                .collect::<Result<_, _>>()?,
+            (_, Some(command)) => split_command(command),
+            _ => return Err(format!("entry of {} without arguments or command", file)),
+        };
+        // 源文件单独保存在 file 中
+        if let Some(i) = arguments.iter().skip(1).position(|a| a == file) {
+            arguments.remove(i + 1);
+        }
+        Ok(CompileCommand::new(directory, file, arguments))
+    }
+
+    /// Converts the entry into a json object, using the `command` string form
+    /// instead of the `arguments` list when `is_cmd` is set.
+    pub fn to_json(&self, is_cmd: bool) -> Value {
+        let mut map = Map::new();
+        map.insert("directory".to_string(), Value::String(self.directory.clone()));
+
+        if !is_cmd {
+            let mut args: Vec<Value> = self.arguments.iter().map(|s| Value::String(s.clone())).collect();
+            args.push(Value::String(self.file.clone()));
+            map.insert("arguments".to_string(), Value::Array(args));
+        }else {
+            map.insert("command".to_string(), Value::String(join_command(&self.arguments)));
+            map.insert("output".to_string(), Value::String(
+                PathBuf::from(&self.file)
+                .with_extension("o")
+                .to_str()
+                .expect("output file should be string")
+                .to_string()
+            ));
+        }
+
+        map.insert("file".to_string(), Value::String(self.file.clone()));
+        Value::Object(map)
+    }
+
+    /// Flags of the entry for compile_flags.txt: the compiler, output and
+    /// dependency file flags are dropped and relative include paths are
+    /// rebased on `base`, the directory of compile_flags.txt.
+    pub fn flags(&self, base: &str) -> Vec<String> {
+        let path = |p: &str| -> String {
+            match p.starts_with('/') {
+                true => p.to_string(),
+                false => Parser::relative_path(&Path::new(&self.directory).join(p).to_string_lossy(), base),
+            }
+        };
+
+        let mut flags = Vec::new();
+        let mut iter = self.arguments.iter().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-c" | "-MD" | "-MMD" | "-MP" => {}
+                "-o" | "-MF" | "-MT" | "-MQ" => { iter.next(); }
+                "-I" | "-isystem" | "-iquote" | "-idirafter" | "-include" => {
+                    if let Some(p) = iter.next() {
+                        flags.push(arg.clone());
+                        flags.push(path(p));
+                    }
+                }
+                a if a.starts_with("-I") => flags.push(format!("-I{}", path(&a[2..]))),
+                a => flags.push(a.to_string()),
+            }
+        }
+        flags
+    }
+
+    /// Pretty printed json object, indented as an element of the database array.
+    pub fn to_pretty_string(&self, is_cmd: bool) -> String {
+        let s = serde_json::to_string_pretty(&Value::Array(vec![self.to_json(is_cmd)])).unwrap();
+        // 去掉外层的 "[\n" 和 "\n]"
+        s[2..s.len() - 2].to_string()
+    }
+}
+
+/// Splits a shell command line, honouring quotes and backslashes.
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                arg.get_or_insert_with(String::new).extend(chars.next());
+            }
+            (Some(_), c) => arg.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(arg.take()),
+            (None, c) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+/// Splits a shell command list on the `;`, `&&` and `&` outside quotes,
+/// keeping each command as written.
+pub fn split_list(line: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let mut quote: Option<char> = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                chars.next();
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ';' | '&') => {
+                commands.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    commands.push(&line[start..]);
+    // `&&` 之间留下的空串
+    commands.retain(|s| !s.is_empty());
+    commands
+}
+
+/// Joins arguments into a command line which `split_command` splits back,
+/// single quoting those with whitespace, quotes or backslashes.
+pub fn join_command<S: AsRef<str>>(args: &[S]) -> String {
+    let quote = |arg: &str| -> String {
+        let plain = !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "'\"\\".contains(c));
+        match plain {
+            true => arg.to_string(),
+            false => format!("'{}'", arg.replace('\'', "'\\''")),
+        }
+    };
+    args.iter().map(|a| quote(a.as_ref())).collect::<Vec<String>>().join(" ")
+}
+
+/// Reads the entries of a compile_commands.json.
+pub fn read_database(path: &str) -> Result<Vec<CompileCommand>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let value: Value = serde_json::from_str(&content).map_err(|e| format!("invalid database {}: {}", path, e))?;
+    value.as_array()
+        .ok_or(format!("invalid database {}: not an array", path))?
+        .iter()
+        .map(|v| CompileCommand::from_json(v).map_err(|e| format!("invalid database {}: {}", path, e)))
+        .collect()
+}
+
+/// Writes entries as a compile_commands.json.
+pub fn write_database(path: &str, entries: &[CompileCommand], is_cmd: bool) -> Result<(), String> {
+    let items: Vec<String> = entries.iter().map(|e| e.to_pretty_string(is_cmd)).collect();
+    let content = format!("[\n{}\n]", items.join(",\n"));
+    fs::write(path, content).map_err(|e| format!("can't write {}: {}", path, e))
+}
+
+/// Merges databases, an entry of a later database replacing the one of an
+/// earlier database for the same directory and file in place.
+pub fn merge(databases: Vec<Vec<CompileCommand>>) -> Vec<CompileCommand> {
+    let mut entries: Vec<CompileCommand> = Vec::new();
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+    for entry in databases.into_iter().flatten() {
+        let key = (entry.directory.clone(), entry.file.clone());
+        match index.get(&key) {
+            Some(&i) => entries[i] = entry,
+            None => {
+                index.insert(key, entries.len());
+                entries.push(entry);
+            }
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_json() {
+        let command = CompileCommand::new("/coder", "main.c", vec!["gcc".to_string(), "-DX".to_string()]);
+        assert_eq!(CompileCommand::from_json(&command.to_json(false)), Ok(command.clone()));
+
+        let value = serde_json::json!({
+            "directory": "/coder",
+            "command": "gcc -DMSG='\"a b\"' -c main.c",
+            "file": "main.c",
+        });
+        let command = CompileCommand::from_json(&value).unwrap();
+        assert_eq!(command.arguments, ["gcc", "-DMSG=\"a b\"", "-c"]);
+
+        // 跟踪得到的参数原样保存, 写成 command 时需要加引号
+        let command = CompileCommand::new("/coder", "main.c", vec!["gcc".to_string(), "-DNAME=a b".to_string()]);
+        assert_eq!(command.to_json(true)["command"], "gcc '-DNAME=a b'");
+        assert_eq!(CompileCommand::from_json(&command.to_json(true)).unwrap().arguments, command.arguments);
+        assert!(CompileCommand::from_json(&serde_json::json!({"file": "a.c"})).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let entry = |file: &str, flag: &str| CompileCommand::new("/coder", file, vec!["gcc".to_string(), flag.to_string()]);
+        let merged = merge(vec![
+            vec![entry("a.c", "-O0"), entry("b.c", "-O0")],
+            vec![entry("a.c", "-O2"), entry("c.c", "-O2")],
+        ]);
+        assert_eq!(merged, [entry("a.c", "-O2"), entry("b.c", "-O0"), entry("c.c", "-O2")]);
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("  gcc  -I\"my dir\" 'a\\b' c\\ d \"\" "), ["gcc", "-Imy dir", "a\\b", "c d", ""]);
+
+        let args = ["gcc", "-DMSG=\"a b\"", "-Imy dir", "it's", "a\\b", ""];
+        assert_eq!(join_command(&args), "gcc '-DMSG=\"a b\"' '-Imy dir' 'it'\\''s' 'a\\b' ''");
+        assert_eq!(split_command(&join_command(&args)), args);
+    }
+
+    #[test]
+    fn test_split_list() {
+        assert_eq!(split_list("set -e;  gcc -c a.c && echo ok"), ["set -e", "  gcc -c a.c ", " echo ok"]);
+        assert_eq!(split_list("gcc '-DKBUILD_MODNAME=\"a;b\"' -c \\; a.c; true"), ["gcc '-DKBUILD_MODNAME=\"a;b\"' -c \\; a.c", " true"]);
+    }
+
+    #[test]
+    fn test_flags() {
+        let arguments = ["gcc", "-Iinclude", "-I", "/usr/include/foo", "-DDEBUG", "-c", "-o", "main.o", "-MMD", "-MF", "main.d", "-isystem", "../sdk"];
+        let command = CompileCommand::new("/coder/build", "../src/main.c", arguments.iter().map(|s| s.to_string()).collect());
+        assert_eq!(command.flags("/coder"), [
+            "-Ibuild/include", "-I", "/usr/include/foo", "-DDEBUG", "-isystem", "sdk",
+        ]);
+    }
+}
@@ -0,0 +1,316 @@
+use std::{collections::BTreeMap, env, fs, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+use crate::{filter::Filter, includes, remap::Remap, rewrite::Rewriter};
+
+/// Name of the project-level config file, looked up from the working
+/// directory upward.
+pub const FILE_NAME: &str = ".ccjson.toml";
+
+/// Form of the entries written to compile_commands.json.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// `arguments` list.
+    Arguments,
+    /// `command` string.
+    Command,
+    /// compile_flags.txt with the flags most entries share.
+    #[serde(rename = "compile-flags")]
+    CompileFlags,
+}
+
+/// How paths in the entries are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Paths {
+    Absolute,
+    /// Relative to the build directory.
+    Relative,
+    /// Relative to the build directory when inside it, absolute otherwise.
+    RelativeWithinRoot,
+}
+
+/// Behaviour of ccjson, read from `.ccjson.toml` and
+/// `$XDG_CONFIG_HOME/ccjson/config.toml`, then overridden by the CLI.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Build path of the project.
+    pub directory: Option<String>,
+    /// Directory for compile_commands.json.
+    pub output: Option<String>,
+    pub format: Format,
+    pub paths: Paths,
+    /// Dialect of the build log, detected when not set.
+    pub dialect: Option<String>,
+    /// Compiler names, also matched with a cross prefix (`arm-none-eabi-gcc`)
+    /// or a version suffix (`gcc-12`).
+    pub compilers: Vec<String>,
+    /// Launchers put before the compiler, dropped from the arguments.
+    pub wrappers: Vec<String>,
+    pub source_extensions: Vec<String>,
+    /// Filter rules, see [`crate::filter::Rule`] for the syntax.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Resolves symlinks through the filesystem for the paths which exist.
+    pub resolve_symlinks: bool,
+    /// Keeps the build directory as seen from `$PWD` when resolving symlinks.
+    pub keep_root: bool,
+    /// Path prefix maps, `/from=/to`.
+    pub remap: Vec<String>,
+    /// Adds entries for the headers included by the translation units.
+    pub headers: bool,
+    /// Queries the compilers for their built-in include directories.
+    pub system_includes: Option<includes::Mode>,
+    pub rewrite: Rewrite,
+    pub target: Target,
+    pub clangd: Clangd,
+    pub check: Check,
+}
+
+/// Limits of `ccjson check` and `--verify`, the `[check]` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Check {
+    /// Checks the generated database, as `--verify`.
+    pub verify: bool,
+    pub max_missing_directories: usize,
+    pub max_missing_files: usize,
+    /// No limit when not set.
+    pub max_missing_includes: Option<usize>,
+}
+
+/// The `.clangd` written next to compile_commands.json, the `[clangd]` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Clangd {
+    pub generate: bool,
+    /// Diagnostics for `Diagnostics: Suppress:`.
+    pub suppress: Vec<String>,
+}
+
+/// Rules rewriting the arguments of every entry, the `[rewrite]` table.
+/// Flags are matched exactly, or by a regex when written `re:<regex>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rewrite {
+    /// Built-in rule sets, such as `gcc-to-clang`.
+    pub profiles: Vec<String>,
+    pub remove: Vec<String>,
+    pub replace: BTreeMap<String, String>,
+    pub prepend: Vec<String>,
+    pub append: Vec<String>,
+}
+
+/// Target triples of cross compilers, the `[target]` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Target {
+    /// Adds `--target=<triple>` to the entries of cross compilers.
+    pub inject: bool,
+    /// Compilers, by name, whose target is left to clangd.
+    pub skip: Vec<String>,
+    /// Triples of compilers without a cross prefix, by compiler name.
+    pub triples: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let strings = |s: &[&str]| -> Vec<String> { s.iter().map(|s| s.to_string()).collect() };
+        Config {
+            directory: None,
+            output: None,
+            format: Format::Arguments,
+            paths: Paths::Relative,
+            dialect: None,
+            compilers: strings(&["gcc", "g++", "clang", "clang++", "cc", "c++"]),
+            wrappers: strings(&["ccache", "sccache", "distcc", "icecc"]),
+            source_extensions: strings(&[".c", ".cc", ".cpp", ".cxx"]),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            resolve_symlinks: false,
+            keep_root: false,
+            remap: Vec::new(),
+            headers: false,
+            system_includes: None,
+            rewrite: Rewrite::default(),
+            target: Target::default(),
+            clangd: Clangd::default(),
+            check: Check::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the user config and the project config found from `dir` upward,
+    /// keys of the project config taking precedence, tables merged key by key.
+    pub fn load(dir: &Path) -> Result<Config, String> {
+        let mut table = Table::new();
+        let files = [Config::user_file(), Config::project_file(dir)];
+        for file in files.iter().flatten() {
+            let content = fs::read_to_string(file)
+                .map_err(|e| format!("can't read {}: {}", file.display(), e))?;
+            let mut t: Table = content.parse()
+                .map_err(|e| format!("invalid config {}: {}", file.display(), e))?;
+
+            // 配置文件中的相对路径相对于配置文件所在目录
+            let base = file.parent().unwrap_or(Path::new("."));
+            for key in ["directory", "output"] {
+                if let Some(toml::Value::String(p)) = t.get_mut(key) {
+                    if Path::new(p.as_str()).is_relative() {
+                        *p = base.join(p.as_str()).to_string_lossy().into_owned();
+                    }
+                }
+            }
+            Config::merge(&mut table, t);
+        }
+
+        Config::from_table(table)
+    }
+
+    /// Merges `other` into `table`, recursing into tables present in both,
+    /// other values of `other` replacing those of `table`.
+    fn merge(table: &mut Table, other: Table) {
+        for (key, value) in other {
+            match (table.get_mut(&key), value) {
+                (Some(toml::Value::Table(t)), toml::Value::Table(o)) => Config::merge(t, o),
+                (_, value) => {
+                    table.insert(key, value);
+                }
+            }
+        }
+    }
+
+    pub fn from_table(table: Table) -> Result<Config, String> {
+        table.try_into().map_err(|e: toml::de::Error| format!("invalid config: {}", e))
+    }
+
+    /// `.ccjson.toml` in `dir` or its nearest ancestor having one.
+    pub fn project_file(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|d| d.join(FILE_NAME))
+            .find(|f| f.is_file())
+    }
+
+    /// `$XDG_CONFIG_HOME/ccjson/config.toml`, `~/.config` when unset.
+    pub fn user_file() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(d) if !d.is_empty() => PathBuf::from(d),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("ccjson").join("config.toml")).filter(|f| f.is_file())
+    }
+
+    pub fn filter(&self) -> Result<Filter, String> {
+        let mut filter = Filter::new();
+        for spec in &self.include {
+            filter.include(spec)?;
+        }
+        for spec in &self.exclude {
+            filter.exclude(spec)?;
+        }
+        Ok(filter)
+    }
+
+    pub fn remap(&self) -> Result<Remap, String> {
+        let mut remap = Remap::new();
+        for spec in &self.remap {
+            remap.add(spec)?;
+        }
+        Ok(remap)
+    }
+
+    pub fn rewriter(&self) -> Result<Rewriter, String> {
+        Rewriter::from_config(&self.rewrite)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap()
+    }
+
+    /// Basename of a compiler without its version suffix.
+    fn compiler_name(s: &str) -> &str {
+        let name = s.rsplit('/').next().unwrap_or(s);
+        // 去掉版本号后缀, 如 gcc-12, clang-17
+        match name.rsplit_once('-') {
+            Some((n, v)) if !v.is_empty() && v.chars().all(|c| c.is_ascii_digit() || c == '.') => n,
+            _ => name,
+        }
+    }
+
+    pub fn is_compiler(&self, s: &str) -> bool {
+        let name = Config::compiler_name(s);
+        self.compilers.iter().any(|c| name == c || name.ends_with(&format!("-{}", c)))
+    }
+
+    /// Cross prefix of a compiler, `arm-none-eabi` for `arm-none-eabi-gcc`.
+    pub fn cross_prefix<'a>(&self, s: &'a str) -> Option<&'a str> {
+        let name = Config::compiler_name(s);
+        self.compilers.iter()
+            .filter_map(|c| name.strip_suffix(c.as_str())?.strip_suffix('-'))
+            .find(|p| !p.is_empty())
+    }
+
+    pub fn is_wrapper(&self, s: &str) -> bool {
+        let name = s.rsplit('/').next().unwrap_or(s);
+        self.wrappers.iter().any(|w| name == w)
+    }
+
+    pub fn is_source(&self, s: &str) -> bool {
+        self.source_extensions.iter().any(|e| s.ends_with(e.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_table() {
+        let table: Table = "format = \"command\"\ncompilers = [\"xcc\"]\n".parse().unwrap();
+        let config = Config::from_table(table).unwrap();
+        assert_eq!(config.format, Format::Command);
+        assert_eq!(config.compilers, ["xcc"]);
+        assert_eq!(config.source_extensions, Config::default().source_extensions);
+
+        let table: Table = "[rewrite]\nprofiles = [\"gcc-to-clang\"]\nreplace = { \"-O3\" = \"-O2\" }\n".parse().unwrap();
+        let config = Config::from_table(table).unwrap();
+        assert_eq!(config.rewrite.profiles, ["gcc-to-clang"]);
+        assert_eq!(config.rewrite.replace["-O3"], "-O2");
+        assert_eq!(Config::from_table(config.to_toml().parse().unwrap()).unwrap(), config);
+
+        let table: Table = "compiler = [\"xcc\"]\n".parse().unwrap();
+        assert!(Config::from_table(table).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut table: Table = "format = \"command\"\n[rewrite]\nprofiles = [\"gcc-to-clang\"]\nremove = [\"-Werror\"]\n".parse().unwrap();
+        let project: Table = "[rewrite]\nremove = [\"-flto\"]\n".parse().unwrap();
+        Config::merge(&mut table, project);
+        let config = Config::from_table(table).unwrap();
+        assert_eq!(config.format, Format::Command);
+        assert_eq!(config.rewrite.profiles, ["gcc-to-clang"]);
+        assert_eq!(config.rewrite.remove, ["-flto"]);
+    }
+
+    #[test]
+    fn test_is_compiler() {
+        let config = Config::default();
+        assert!(config.is_compiler("gcc"));
+        assert!(config.is_compiler("/usr/bin/cc"));
+        assert!(config.is_compiler("arm-none-eabi-gcc"));
+        assert!(config.is_compiler("/opt/llvm/bin/clang++-17"));
+        assert!(config.is_compiler("aarch64-linux-gnu-gcc-12.2.1"));
+        assert!(!config.is_compiler("main.cc"));
+        assert!(!config.is_compiler("CC=gcc"));
+        assert_eq!(config.cross_prefix("/opt/bin/aarch64-linux-gnu-gcc-12"), Some("aarch64-linux-gnu"));
+        assert_eq!(config.cross_prefix("arm-none-eabi-g++"), Some("arm-none-eabi"));
+        assert_eq!(config.cross_prefix("/usr/bin/clang-17"), None);
+        assert!(config.is_wrapper("/usr/bin/ccache"));
+        assert!(config.is_source("main.cpp"));
+    }
+}
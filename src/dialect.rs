@@ -0,0 +1,428 @@
+use regex::Regex;
+
+use crate::{command, config::Config};
+
+/// Names of the built-in dialects, see [`by_name`].
+pub const NAMES: [&str; 5] = ["make", "ninja", "kbuild", "cmake", "meson"];
+
+/// Parser state visible to a dialect while it handles a line.
+pub struct State<'a> {
+    /// Compilers, wrappers and source extensions to recognise.
+    pub config: &'a Config,
+    /// Build directory of the project, `directory` of every entry.
+    pub build_dir: &'a str,
+    /// Directory the current commands run in.
+    pub directory: &'a str,
+    /// Directories saved by nested `Enter`s, innermost last.
+    pub directories: &'a [String],
+}
+
+/// What a dialect makes of a line of build log. Relative directories are
+/// resolved against the current directory by the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// The line is known but carries nothing of interest.
+    Skip,
+    /// The following commands run in a nested directory, until `Leave`.
+    Enter(String),
+    /// Back to the directory before the last `Enter`.
+    Leave,
+    /// The following commands run in another directory.
+    Cd(String),
+    /// A command which may invoke a compiler, run in `directory` if given
+    /// and in the current directory otherwise.
+    Command {
+        directory: Option<String>,
+        command: String,
+    },
+}
+
+/// Handles the lines of a kind of build log.
+///
+/// The parser hands every logical line to its dialects in turn, the first
+/// one returning `Some` decides what the line is. Lines no dialect knows are
+/// parsed as plain commands.
+pub trait Dialect {
+    fn name(&self) -> &str;
+
+    fn parse(&mut self, line: &str, state: &State) -> Option<Action>;
+
+    /// Whether a sampled line is typical of this dialect, see [`detect`].
+    fn detect(&self, _line: &str) -> bool {
+        false
+    }
+}
+
+/// Returns the built-in dialect called `name`.
+pub fn by_name(name: &str) -> Option<Box<dyn Dialect>> {
+    match name {
+        "make" => Some(Box::new(Make::new())),
+        "ninja" => Some(Box::new(Ninja::new())),
+        "kbuild" => Some(Box::new(Kbuild::new())),
+        "cmake" => Some(Box::new(CMake::new())),
+        "meson" => Some(Box::new(Meson::new())),
+        _ => None,
+    }
+}
+
+/// Picks the built-in dialects typical of any of the sampled lines, in the
+/// order they should be added to the parser. Make is the parser's fallback
+/// and never returned.
+pub fn detect<S: AsRef<str>>(lines: &[S]) -> Vec<Box<dyn Dialect>> {
+    // 越具体的方言越后添加, 以便被优先尝试
+    let mut candidates: Vec<Box<dyn Dialect>> = vec![
+        Box::new(CMake::new()),
+        Box::new(Ninja::new()),
+        Box::new(Meson::new()),
+        Box::new(Kbuild::new()),
+    ];
+    candidates.retain(|d| lines.iter().any(|l| d.detect(l.as_ref())));
+
+    // meson 已经包含了 ninja
+    if candidates.iter().any(|d| d.name() == "meson") {
+        candidates.retain(|d| d.name() != "ninja");
+    }
+    candidates
+}
+
+/// make, also `sh -x` shell scripts which `cd` around instead of nesting make.
+pub struct Make {
+    directory: Regex,
+    cd: Regex,
+}
+
+impl Make {
+    pub fn new() -> Make {
+        Make {
+            // make -w: "make[1]: Entering directory '/path'", 旧版本使用 `/path'
+            directory: Regex::new(r"^\S*make(?:\[\d+\])?: (Entering|Leaving) directory [`'‘](.*)['’]\s*$").unwrap(),
+            // sh -x: "+ cd /path", 嵌套的子 shell 为 "++ cd /path"
+            cd: Regex::new(r#"^\++\s+cd\s+['"]?([^'"\s]+)"#).unwrap(),
+        }
+    }
+}
+
+impl Default for Make {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dialect for Make {
+    fn name(&self) -> &str {
+        "make"
+    }
+
+    fn parse(&mut self, line: &str, _state: &State) -> Option<Action> {
+        if let Some(caps) = self.directory.captures(line) {
+            return match caps.get(1).unwrap().as_str() {
+                "Entering" => Some(Action::Enter(caps.get(2).unwrap().as_str().to_string())),
+                _ => Some(Action::Leave),
+            };
+        }
+
+        self.cd.captures(line).map(|caps| Action::Cd(caps.get(1).unwrap().as_str().to_string()))
+    }
+}
+
+/// `ninja -v` and `ninja -t commands`. Ninja runs every command in its build
+/// directory, which only changes on the `ninja: Entering directory` line
+/// printed for `-C`.
+pub struct Ninja {
+    directory: Regex,
+    progress: Regex,
+}
+
+impl Ninja {
+    pub fn new() -> Ninja {
+        Ninja {
+            directory: Regex::new(r"^ninja: Entering directory [`'](.*)'\s*$").unwrap(),
+            progress: Regex::new(r"^\[\d+/\d+\]\s+").unwrap(),
+        }
+    }
+}
+
+impl Default for Ninja {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dialect for Ninja {
+    fn name(&self) -> &str {
+        "ninja"
+    }
+
+    fn parse(&mut self, line: &str, _state: &State) -> Option<Action> {
+        if line.starts_with("# ninja log v") {
+            eprintln!("Warning: .ninja_log only records command hashes, use `ninja -t commands` instead.");
+            return Some(Action::Skip);
+        }
+
+        if let Some(caps) = self.directory.captures(line) {
+            return Some(Action::Cd(caps.get(1).unwrap().as_str().to_string()));
+        }
+
+        // 去掉 "[12/340] " 形式的进度前缀
+        let prefix = self.progress.find(line)?;
+        Some(Action::Command {
+            directory: None,
+            command: line[prefix.end()..].to_string(),
+        })
+    }
+
+    fn detect(&self, line: &str) -> bool {
+        self.progress.is_match(line) || self.directory.is_match(line) || line.starts_with("# ninja log v")
+    }
+}
+
+/// Linux Kbuild `V=1` logs and `cmd_foo.o := ...` lines of `.*.o.cmd` files.
+/// Kbuild chains the compiler with other tools (`; objtool ...`), so only the
+/// segment running the compiler is kept.
+pub struct Kbuild {
+    cmd: Regex,
+    quiet: Regex,
+    // 是否已经提示过 Kbuild 静默模式的日志
+    quiet_warned: bool,
+}
+
+impl Kbuild {
+    pub fn new() -> Kbuild {
+        Kbuild {
+            cmd: Regex::new(r"^(?:saved)?cmd_\S+\s+:=\s*").unwrap(),
+            // 静默模式只输出 "  CC      drivers/foo.o", 没有编译命令
+            quiet: Regex::new(r"^\s+CC(?: \[M\])?\s+\S+\.o\s*$").unwrap(),
+            quiet_warned: false,
+        }
+    }
+}
+
+impl Default for Kbuild {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dialect for Kbuild {
+    fn name(&self) -> &str {
+        "kbuild"
+    }
+
+    fn parse(&mut self, line: &str, state: &State) -> Option<Action> {
+        if self.quiet.is_match(line) {
+            if !self.quiet_warned {
+                self.quiet_warned = true;
+                eprintln!("Warning: Kbuild quiet log has no commands, rebuild with V=1 or scan the .cmd files.");
+            }
+            return Some(Action::Skip);
+        }
+
+        let line = self.cmd.replace(line, "");
+        command::split_list(&line).into_iter()
+            .find(|s| {
+                let tokens: Vec<&str> = s.split_whitespace().collect();
+                tokens.iter().any(|t| state.config.is_compiler(t)) && tokens.iter().any(|t| state.config.is_source(t))
+            })
+            .map(|s| Action::Command {
+                directory: None,
+                command: s.to_string(),
+            })
+    }
+
+    fn detect(&self, line: &str) -> bool {
+        self.cmd.is_match(line)
+            || self.quiet.is_match(line)
+            || line.contains("scripts/Makefile.build")
+            || line.contains("-D__KERNEL__")
+    }
+}
+
+/// CMake Makefile generators run with `make VERBOSE=1`. Progress lines such
+/// as `[ 42%] Building C object ...` are skipped, and a command prefixed with
+/// `cd /build/dir && ` runs in that directory without changing the one
+/// tracked for the following lines.
+pub struct CMake {
+    progress: Regex,
+    cd: Regex,
+}
+
+impl CMake {
+    pub fn new() -> CMake {
+        CMake {
+            progress: Regex::new(r"^\[\s*\d+%\]").unwrap(),
+            cd: Regex::new(r"^\s*cd\s+(\S+)\s*&&\s*(.*)").unwrap(),
+        }
+    }
+}
+
+impl Default for CMake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dialect for CMake {
+    fn name(&self) -> &str {
+        "cmake"
+    }
+
+    fn parse(&mut self, line: &str, _state: &State) -> Option<Action> {
+        if self.progress.is_match(line) {
+            return Some(Action::Skip);
+        }
+
+        self.cd.captures(line).map(|caps| Action::Command {
+            directory: Some(caps.get(1).unwrap().as_str().to_string()),
+            command: caps.get(2).unwrap().as_str().to_string(),
+        })
+    }
+
+    fn detect(&self, line: &str) -> bool {
+        self.progress.is_match(line) || line.contains("/CMakeFiles/")
+    }
+}
+
+/// Meson, whose ninja backend is run with `ninja -v`, custom commands are
+/// prefixed with `cd` like CMake's.
+pub struct Meson {
+    ninja: Ninja,
+    cmake: CMake,
+}
+
+impl Meson {
+    pub fn new() -> Meson {
+        Meson {
+            ninja: Ninja::new(),
+            cmake: CMake::new(),
+        }
+    }
+}
+
+impl Default for Meson {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dialect for Meson {
+    fn name(&self) -> &str {
+        "meson"
+    }
+
+    fn parse(&mut self, line: &str, state: &State) -> Option<Action> {
+        match self.ninja.parse(line, state) {
+            Some(Action::Command { command, .. }) => {
+                self.cmake.parse(&command, state).or(Some(Action::Command { directory: None, command }))
+            }
+            res => res,
+        }
+    }
+
+    fn detect(&self, line: &str) -> bool {
+        // meson 的目标文件放在 "<target>.p/" 目录下
+        self.ninja.progress.is_match(line) && (line.contains(".p/") || line.contains("meson"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(config: &Config) -> State<'_> {
+        State {
+            config,
+            build_dir: "/coder/build",
+            directory: "/coder/build",
+            directories: &[],
+        }
+    }
+
+    fn command(directory: Option<&str>, command: &str) -> Option<Action> {
+        Some(Action::Command {
+            directory: directory.map(|s| s.to_string()),
+            command: command.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_detect() {
+        let names = |lines: &[&str]| -> Vec<String> {
+            detect(lines).iter().map(|d| d.name().to_string()).collect()
+        };
+
+        assert!(names(&["gcc -c main.c", "make[1]: Entering directory '/coder'"]).is_empty());
+        assert_eq!(names(&["[1/2] cc -c ../foo.c -o foo.o"]), ["ninja"]);
+        assert_eq!(names(&["[1/2] cc -MQ foo.p/foo.c.o -o foo.p/foo.c.o -c ../foo.c"]), ["meson"]);
+        assert_eq!(names(&["[ 42%] Building C object src/CMakeFiles/foo.dir/foo.c.o"]), ["cmake"]);
+        assert_eq!(names(&["[ 42%] Building C object src/CMakeFiles/foo.dir/foo.c.o", "  CC [M]  drivers/foo.o"]), ["cmake", "kbuild"]);
+    }
+
+    #[test]
+    fn test_make() {
+        let config = Config::default();
+        let state = state(&config);
+        let mut make = Make::new();
+        assert_eq!(make.parse("make[1]: Entering directory `/coder/build/src'\n", &state), Some(Action::Enter("/coder/build/src".to_string())));
+        assert_eq!(make.parse("make: Leaving directory '/coder/build'\n", &state), Some(Action::Leave));
+        assert_eq!(make.parse("++ cd lib\n", &state), Some(Action::Cd("lib".to_string())));
+        assert_eq!(make.parse("gcc -c main.c\n", &state), None);
+    }
+
+    #[test]
+    fn test_ninja() {
+        let config = Config::default();
+        let state = state(&config);
+        let mut ninja = Ninja::new();
+        assert_eq!(ninja.parse("ninja: Entering directory `build'\n", &state), Some(Action::Cd("build".to_string())));
+        assert_eq!(ninja.parse("# ninja log v5\n", &state), Some(Action::Skip));
+        assert_eq!(
+            ninja.parse("[12/340] /usr/bin/cc -Iinclude -c ../src/foo.c -o src/foo.o\n", &state),
+            command(None, "/usr/bin/cc -Iinclude -c ../src/foo.c -o src/foo.o\n")
+        );
+        assert_eq!(ninja.parse("/usr/bin/cc -c ../src/foo.c\n", &state), None);
+    }
+
+    #[test]
+    fn test_kbuild() {
+        let config = Config::default();
+        let state = state(&config);
+        let mut kbuild = Kbuild::new();
+
+        let line = "savedcmd_drivers/foo.o := gcc -Wp,-MMD,drivers/.foo.o.d -nostdinc -c -o drivers/foo.o drivers/foo.c  ; ./tools/objtool/objtool --orc drivers/foo.o\n";
+        assert_eq!(kbuild.parse(line, &state), command(None, "gcc -Wp,-MMD,drivers/.foo.o.d -nostdinc -c -o drivers/foo.o drivers/foo.c  "));
+
+        let line = "  set -e;  gcc -nostdinc -c -o init/main.o init/main.c; ./scripts/check-local-export init/main.o\n";
+        assert_eq!(kbuild.parse(line, &state), command(None, "  gcc -nostdinc -c -o init/main.o init/main.c"));
+
+        let line = "savedcmd_drivers/foo.o := gcc -DKBUILD_MODNAME='\"a;b\"' -c -o drivers/foo.o drivers/foo.c; true\n";
+        assert_eq!(kbuild.parse(line, &state), command(None, "gcc -DKBUILD_MODNAME='\"a;b\"' -c -o drivers/foo.o drivers/foo.c"));
+
+        assert_eq!(kbuild.parse("  CC [M]  drivers/foo.o\n", &state), Some(Action::Skip));
+        assert_eq!(kbuild.parse("make -f ./scripts/Makefile.build obj=drivers\n", &state), None);
+    }
+
+    #[test]
+    fn test_cmake() {
+        let config = Config::default();
+        let state = state(&config);
+        let mut cmake = CMake::new();
+        assert_eq!(cmake.parse("[ 42%] Building C object src/CMakeFiles/foo.dir/foo.c.o\n", &state), Some(Action::Skip));
+        assert_eq!(
+            cmake.parse("cd /coder/build/src && /usr/bin/cc -c /coder/src/foo.c\n", &state),
+            command(Some("/coder/build/src"), "/usr/bin/cc -c /coder/src/foo.c")
+        );
+        assert_eq!(cmake.parse("make[2]: Entering directory '/coder/build'\n", &state), None);
+    }
+
+    #[test]
+    fn test_meson() {
+        let config = Config::default();
+        let state = state(&config);
+        let mut meson = Meson::new();
+        assert_eq!(
+            meson.parse("[3/8] cd /coder/src && /usr/bin/cc -c gen.c\n", &state),
+            command(Some("/coder/src"), "/usr/bin/cc -c gen.c")
+        );
+        assert_eq!(meson.parse("[4/8] cc -c ../foo.c\n", &state), command(None, "cc -c ../foo.c\n"));
+    }
+}
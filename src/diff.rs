@@ -0,0 +1,163 @@
+use std::{collections::{BTreeMap, HashMap}, fmt, path::Path};
+
+use serde::Serialize;
+
+use crate::{command::CompileCommand, parser::Parser};
+
+/// A translation unit, entries being matched by directory and file.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Unit {
+    pub directory: String,
+    pub file: String,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Path::new(&self.directory).join(&self.file).display())
+    }
+}
+
+/// Arguments of a unit found in both databases which differ.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Changed {
+    #[serde(flatten)]
+    pub unit: Unit,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Same arguments in another order.
+    pub reordered: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Diff {
+    pub added: Vec<Unit>,
+    pub removed: Vec<Unit>,
+    pub changed: Vec<Changed>,
+}
+
+/// Arguments of `a` missing in `b`, counting repeated ones.
+fn difference(a: &[String], b: &[String]) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for arg in b {
+        *counts.entry(arg).or_insert(0) += 1;
+    }
+    a.iter().filter(|arg| match counts.get_mut(arg.as_str()) {
+        Some(n) if *n > 0 => { *n -= 1; false }
+        _ => true,
+    }).cloned().collect()
+}
+
+/// Compares two databases. Entries are matched by the normalised absolute
+/// path of their file, as clangd does, so `x.c` in `/a` and `/a/x.c` are the
+/// same unit. An entry repeated in a database is compared by its last
+/// occurrence.
+pub fn diff(old: &[CompileCommand], new: &[CompileCommand]) -> Diff {
+    let index = |entries: &[CompileCommand]| -> BTreeMap<String, (Unit, Vec<String>)> {
+        entries.iter()
+            .map(|e| {
+                let path = Parser::norm_path(&Path::new(&e.directory).join(&e.file).to_string_lossy());
+                (path, (Unit { directory: e.directory.clone(), file: e.file.clone() }, e.arguments.clone()))
+            })
+            .collect()
+    };
+    let old = index(old);
+    let new = index(new);
+
+    let mut diff = Diff::default();
+    for (path, (unit, old_args)) in &old {
+        let Some((new_unit, new_args)) = new.get(path) else {
+            diff.removed.push(unit.clone());
+            continue;
+        };
+        if old_args == new_args {
+            continue;
+        }
+        let added = difference(new_args, old_args);
+        let removed = difference(old_args, new_args);
+        let reordered = added.is_empty() && removed.is_empty();
+        diff.changed.push(Changed { unit: new_unit.clone(), added, removed, reordered });
+    }
+    diff.added = new.iter()
+        .filter(|(path, _)| !old.contains_key(*path))
+        .map(|(_, (unit, _))| unit.clone())
+        .collect();
+    diff
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for unit in &self.removed {
+            writeln!(f, "- {}", unit)?;
+        }
+        for unit in &self.added {
+            writeln!(f, "+ {}", unit)?;
+        }
+        for changed in &self.changed {
+            writeln!(f, "~ {}", changed.unit)?;
+            for arg in &changed.removed {
+                writeln!(f, "    - {}", arg)?;
+            }
+            for arg in &changed.added {
+                writeln!(f, "    + {}", arg)?;
+            }
+            if changed.reordered {
+                writeln!(f, "    arguments reordered")?;
+            }
+        }
+        writeln!(
+            f, "{} added, {} removed, {} changed translation units",
+            self.added.len(), self.removed.len(), self.changed.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, args: &[&str]) -> CompileCommand {
+        CompileCommand::new("/coder", file, args.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = [
+            entry("a.c", &["gcc", "-O0", "-DX", "-DX"]),
+            entry("b.c", &["gcc", "-DA", "-DB"]),
+            entry("c.c", &["gcc"]),
+            entry("gone.c", &["gcc"]),
+        ];
+        let new = [
+            entry("new.c", &["gcc"]),
+            entry("a.c", &["gcc", "-O2", "-DX"]),
+            entry("b.c", &["gcc", "-DB", "-DA"]),
+            entry("c.c", &["gcc"]),
+        ];
+        let diff = diff(&old, &new);
+        assert_eq!(diff.removed, [Unit { directory: "/coder".to_string(), file: "gone.c".to_string() }]);
+        assert_eq!(diff.added[0].file, "new.c");
+        assert_eq!(diff.changed.len(), 2);
+        assert_eq!(diff.changed[0].added, ["-O2"]);
+        assert_eq!(diff.changed[0].removed, ["-O0", "-DX"]);
+        assert!(!diff.changed[0].reordered);
+        assert!(diff.changed[1].reordered);
+
+        let text = diff.to_string();
+        assert!(text.starts_with("- /coder/gone.c\n+ /coder/new.c\n~ /coder/a.c\n    - -O0\n    - -DX\n    + -O2\n"));
+        assert!(text.ends_with("1 added, 1 removed, 2 changed translation units\n"));
+
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["changed"][1]["file"], "b.c");
+        assert_eq!(json["changed"][1]["reordered"], true);
+
+        let moved = [CompileCommand::new("/coder/build", "../a.c", vec!["gcc".to_string()])];
+        let diff = super::diff(&[entry("/coder/a.c", &["gcc"])], &moved);
+        assert!(diff.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::{command::CompileCommand, parser::Parser};
+
+/// Field of an entry a filter rule is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    File,
+    Directory,
+    Compiler,
+}
+
+/// A single include or exclude pattern.
+///
+/// Written as `[field:][re:]pattern`, where `field` is one of `file` (the
+/// default), `directory` or `compiler`, and the pattern is a glob unless
+/// prefixed with `re:`. Globs support `*`, `**`, `?` and `[...]` and must
+/// match the whole value.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub field: Field,
+    pattern: Regex,
+}
+
+impl Rule {
+    pub fn new(spec: &str) -> Result<Rule, String> {
+        let (field, pattern) = match spec.split_once(':') {
+            Some(("file", p)) => (Field::File, p),
+            Some(("directory", p)) => (Field::Directory, p),
+            Some(("compiler", p)) => (Field::Compiler, p),
+            _ => (Field::File, spec),
+        };
+
+        let regex = match pattern.strip_prefix("re:") {
+            Some(re) => re.to_string(),
+            None => Rule::glob_to_regex(pattern),
+        };
+        let pattern = Regex::new(&regex).map_err(|e| format!("invalid filter {}: {}", spec, e))?;
+        Ok(Rule { field, pattern })
+    }
+
+    fn glob_to_regex(glob: &str) -> String {
+        let mut regex = String::from("^");
+        let mut chars = glob.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    // "**/" 可以匹配零层目录
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    }else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => {
+                    regex.push('[');
+                    if chars.peek() == Some(&'!') {
+                        chars.next();
+                        regex.push('^');
+                    }
+                    for c in chars.by_ref() {
+                        if c == ']' {
+                            break;
+                        }
+                        if c == '\\' {
+                            regex.push('\\');
+                        }
+                        regex.push(c);
+                    }
+                    regex.push(']');
+                }
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        regex
+    }
+
+    /// Whether the rule matches the entry. Files are matched both as written
+    /// in the entry and as normalised absolute paths, compilers both with and
+    /// without their directory.
+    pub fn matches(&self, command: &CompileCommand) -> bool {
+        match self.field {
+            Field::File => {
+                let absolute = Path::new(&command.directory).join(&command.file);
+                self.pattern.is_match(&command.file)
+                    || self.pattern.is_match(&Parser::norm_path(&absolute.to_string_lossy()))
+            }
+            Field::Directory => self.pattern.is_match(&command.directory),
+            Field::Compiler => command.arguments.first().is_some_and(|c| {
+                let name = c.rsplit('/').next().unwrap_or(c);
+                self.pattern.is_match(c) || self.pattern.is_match(name)
+            }),
+        }
+    }
+}
+
+/// Keeps the entries matching any include rule, all when there is none, and
+/// drops those matching an exclude rule.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+
+    pub fn include(&mut self, spec: &str) -> Result<(), String> {
+        self.include.push(Rule::new(spec)?);
+        Ok(())
+    }
+
+    pub fn exclude(&mut self, spec: &str) -> Result<(), String> {
+        self.exclude.push(Rule::new(spec)?);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn matches(&self, command: &CompileCommand) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.matches(command)))
+            && !self.exclude.iter().any(|r| r.matches(command))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(directory: &str, file: &str, cc: &str) -> CompileCommand {
+        CompileCommand::new(directory, file, vec![cc.to_string(), "-c".to_string()])
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        assert_eq!(Rule::glob_to_regex("src/*.c"), "^src/[^/]*\\.c$");
+        assert_eq!(Rule::glob_to_regex("**/gen/**"), "^(?:.*/)?gen/.*$");
+        assert_eq!(Rule::glob_to_regex("a?[!x].c"), "^a[^/][^x]\\.c$");
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut filter = Filter::new();
+        assert!(filter.matches(&command("/coder", "src/main.c", "gcc")));
+
+        filter.exclude("**/third_party/**").unwrap();
+        filter.exclude("re:_gen\\.c$").unwrap();
+        filter.exclude("compiler:*clang*").unwrap();
+        assert!(filter.matches(&command("/coder", "src/main.c", "gcc")));
+        assert!(!filter.matches(&command("/coder", "third_party/zlib/inflate.c", "gcc")));
+        assert!(!filter.matches(&command("/coder", "src/parser_gen.c", "gcc")));
+        assert!(!filter.matches(&command("/coder", "src/main.c", "/usr/bin/clang")));
+
+        filter.include("/coder/src/**").unwrap();
+        filter.include("directory:/other").unwrap();
+        assert!(filter.matches(&command("/coder", "src/main.c", "gcc")));
+        assert!(!filter.matches(&command("/coder", "test/main.c", "gcc")));
+        assert!(filter.matches(&command("/other", "test/main.c", "gcc")));
+        assert!(filter.matches(&command("/coder/build", "../src/main.c", "gcc")));
+
+        assert!(filter.include("re:(").is_err());
+    }
+}
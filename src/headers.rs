@@ -0,0 +1,144 @@
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, fs, path::Path};
+
+use regex::Regex;
+
+use crate::{command::CompileCommand, config::Paths, parser::Parser};
+
+/// A header reached from a translation unit.
+struct Candidate {
+    /// Index of the translation unit.
+    unit: usize,
+    /// Number of includes between the unit and the header.
+    depth: usize,
+}
+
+/// `#include` directives of a file, `true` for the quoted form.
+fn includes(content: &str) -> Vec<(bool, String)> {
+    let re = Regex::new(r#"^\s*#\s*include\s*([<"])([^>"]+)[>"]"#).unwrap();
+    content.lines()
+        .filter_map(|l| re.captures(l))
+        .map(|c| (&c[1] == "\"", c[2].to_string()))
+        .collect()
+}
+
+/// `-I` and `-iquote` directories of an entry, as absolute paths. Quoted
+/// includes look in the `-iquote` ones first.
+fn search_dirs(entry: &CompileCommand) -> (Vec<String>, Vec<String>) {
+    let absolute = |p: &str| Parser::norm_path(&Path::new(&entry.directory).join(p).to_string_lossy());
+    let mut quote = Vec::new();
+    let mut angle = Vec::new();
+    let mut iter = entry.arguments.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-I" => angle.extend(iter.next().map(|p| absolute(p))),
+            "-iquote" => quote.extend(iter.next().map(|p| absolute(p))),
+            a if a.starts_with("-I") => angle.push(absolute(&a[2..])),
+            _ => {}
+        }
+    }
+    (quote, angle)
+}
+
+fn stem(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.split('.').next().unwrap_or(name)
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.split('/').zip(b.split('/')).take_while(|(x, y)| x == y).count()
+}
+
+/// Synthetic entries for the headers included, directly or not, by the
+/// translation units in `entries` and found in their `-I` directories. Each
+/// header takes the flags of the unit matching it best: the one with the
+/// same file stem (`foo.c` for `foo.h`), then the one including it most
+/// directly, then the one closest to it in the tree. `paths` tells how to
+/// write the header paths.
+pub fn infer(entries: &[CompileCommand], paths: Paths) -> Vec<CompileCommand> {
+    let source = |e: &CompileCommand| Parser::norm_path(&Path::new(&e.directory).join(&e.file).to_string_lossy());
+    let known: HashSet<String> = entries.iter().map(source).collect();
+    let mut contents: HashMap<String, Vec<(bool, String)>> = HashMap::new();
+    let mut candidates: BTreeMap<String, Vec<Candidate>> = BTreeMap::new();
+
+    for (unit, entry) in entries.iter().enumerate() {
+        let (quote, angle) = search_dirs(entry);
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut queue = VecDeque::from([(source(entry), 0)]);
+        while let Some((file, depth)) = queue.pop_front() {
+            let directives = contents.entry(file.clone())
+                .or_insert_with(|| fs::read_to_string(&file).map(|c| includes(&c)).unwrap_or_default());
+            let current_dir = file.rsplit_once('/').map_or("", |(d, _)| d).to_string();
+
+            for (quoted, name) in directives.iter() {
+                let mut dirs: Vec<&str> = Vec::new();
+                if *quoted {
+                    dirs.push(&current_dir);
+                    dirs.extend(quote.iter().map(|d| d.as_str()));
+                }
+                dirs.extend(angle.iter().map(|d| d.as_str()));
+
+                let header = dirs.iter()
+                    .map(|d| Parser::norm_path(&format!("{}/{}", d, name)))
+                    .find(|h| Path::new(h).is_file());
+                if let Some(header) = header {
+                    if visited.insert(header.clone()) {
+                        if !known.contains(&header) {
+                            candidates.entry(header.clone()).or_default().push(Candidate { unit, depth: depth + 1 });
+                        }
+                        queue.push_back((header, depth + 1));
+                    }
+                }
+            }
+        }
+    }
+
+    candidates.into_iter().filter_map(|(header, candidates)| {
+        let best = candidates.iter().max_by(|a, b| {
+            let key = |c: &Candidate| {
+                let unit = source(&entries[c.unit]);
+                (stem(&unit) == stem(&header), std::cmp::Reverse(c.depth), common_prefix(&unit, &header))
+            };
+            // 得分相同时保留先出现的编译单元
+            key(a).cmp(&key(b)).then(b.unit.cmp(&a.unit))
+        })?;
+        let entry = &entries[best.unit];
+        let file = Parser::rebase(&header, &entry.directory, paths);
+        Some(CompileCommand::new(&entry.directory, &file, entry.arguments.clone()))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_includes() {
+        let content = "#include <stdio.h>\n  #  include \"foo/bar.h\"\n// #include \"no.h\"\nint x;\n";
+        assert_eq!(includes(content), [(false, "stdio.h".to_string()), (true, "foo/bar.h".to_string())]);
+    }
+
+    #[test]
+    fn test_infer() {
+        let dir = std::env::temp_dir().join("ccjson_headers");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("include")).unwrap();
+        fs::write(dir.join("src/foo.c"), "#include \"foo.h\"\n").unwrap();
+        fs::write(dir.join("src/main.c"), "#include <stdio.h>\n#include \"foo.h\"\n#include <util.h>\n").unwrap();
+        fs::write(dir.join("src/foo.h"), "#include <util.h>\n").unwrap();
+        fs::write(dir.join("include/util.h"), "#include \"foo.h\"\n").unwrap();
+
+        let root = dir.to_str().unwrap();
+        let args = |d: &str| vec!["gcc".to_string(), "-Iinclude".to_string(), d.to_string()];
+        let entries = [
+            CompileCommand::new(root, "src/main.c", args("-DMAIN")),
+            CompileCommand::new(root, "src/foo.c", args("-DFOO")),
+        ];
+        let headers = infer(&entries, Paths::Relative);
+        assert_eq!(headers, [
+            CompileCommand::new(root, "include/util.h", args("-DMAIN")),
+            CompileCommand::new(root, "src/foo.h", args("-DFOO")),
+        ]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashMap, env, path::Path, process::{Command, Stdio}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, parser::Parser};
+
+/// How the built-in include directories of the compilers are made known to
+/// clangd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// Queries every compiler once and appends `-isystem` flags to its
+    /// entries.
+    Isystem,
+    /// Lists the compilers in `.clangd`, for clangd's `--query-driver`.
+    QueryDriver,
+}
+
+pub const MODES: [&str; 2] = ["isystem", "query-driver"];
+
+/// Language passed to `-x` for a source file.
+pub fn language(file: &str) -> &'static str {
+    match file.ends_with(".c") {
+        true => "c",
+        false => "c++",
+    }
+}
+
+/// Parses the output of `cc -E -x c -v /dev/null`.
+pub fn parse_search_list(output: &str) -> Vec<String> {
+    output.lines()
+        .skip_while(|l| !l.starts_with("#include <...> search starts here:"))
+        .skip(1)
+        .take_while(|l| !l.starts_with("End of search list."))
+        .map(|l| l.trim().trim_end_matches(" (framework directory)").to_string())
+        .collect()
+}
+
+/// Runs `compiler -E -x <language> -v /dev/null` for its search list.
+pub fn query(compiler: &str, language: &str) -> Result<Vec<String>, String> {
+    let output = Command::new(compiler)
+        .args(["-E", "-x", language, "-v", "/dev/null"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("can't run {}: {}", compiler, e))?;
+    Ok(parse_search_list(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Search lists already queried, by compiler and language.
+#[derive(Debug, Default)]
+pub struct Cache {
+    lists: HashMap<(String, &'static str), Vec<String>>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Search list of the compiler, empty when it can't be run.
+    pub fn get(&mut self, compiler: &str, language: &'static str) -> &[String] {
+        self.lists.entry((compiler.to_string(), language)).or_insert_with(|| {
+            query(compiler, language).unwrap_or_else(|e| {
+                eprintln!("Warning: {}, its system include paths are unknown", e);
+                Vec::new()
+            })
+        })
+    }
+}
+
+/// Absolute path of a compiler, looked up in `$PATH` when given by name.
+pub fn resolve(compiler: &str, directory: &str) -> String {
+    if compiler.contains('/') {
+        return Parser::norm_path(&Path::new(directory).join(compiler).to_string_lossy());
+    }
+    env::var_os("PATH")
+        .and_then(|paths| env::split_paths(&paths)
+            .map(|p| p.join(compiler))
+            .find(|p| p.is_file()))
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|| compiler.to_string())
+}
+
+/// `--query-driver` glob matching the compiler and its siblings of the same
+/// toolchain, `/opt/gcc/bin/arm-none-eabi-*` for a cross compiler.
+pub fn query_driver_glob(compiler: &str, directory: &str, config: &Config) -> String {
+    let path = resolve(compiler, directory);
+    match (config.cross_prefix(&path), path.rsplit_once('/')) {
+        (Some(prefix), Some((dir, _))) => format!("{}/{}-*", dir, prefix),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_search_list() {
+        let output = "\
+ignoring nonexistent directory \"/usr/local/include/x86_64-linux-gnu\"
+#include \"...\" search starts here:
+#include <...> search starts here:
+ /usr/lib/gcc/x86_64-linux-gnu/12/include
+ /usr/local/include
+ /System/Library/Frameworks (framework directory)
+End of search list.
+# 0 \"/dev/null\"
+";
+        assert_eq!(parse_search_list(output), [
+            "/usr/lib/gcc/x86_64-linux-gnu/12/include",
+            "/usr/local/include",
+            "/System/Library/Frameworks",
+        ]);
+        assert!(parse_search_list("").is_empty());
+    }
+
+    #[test]
+    fn test_query_driver_glob() {
+        let config = Config::default();
+        assert_eq!(
+            query_driver_glob("/opt/gcc/bin/arm-none-eabi-gcc", "/coder", &config),
+            "/opt/gcc/bin/arm-none-eabi-*"
+        );
+        assert_eq!(query_driver_glob("../tools/cc", "/coder/build", &config), "/coder/tools/cc");
+        assert_eq!(language("main.cpp"), "c++");
+    }
+}
@@ -1,18 +1,125 @@
+use std::{collections::HashMap, env};
+
+pub mod check;
+pub mod command;
+pub mod config;
+pub mod dialect;
+pub mod diff;
+pub mod filter;
+pub mod headers;
+pub mod includes;
 pub mod reader;
 pub mod parser;
+pub mod remap;
+pub mod rewrite;
+pub mod stats;
+pub mod target;
 pub mod writer;
+#[cfg(target_os = "linux")]
+pub mod tracer;
 
+/// Statistics of the parser once done, with the exit code of the build.
+fn finish(parser: &parser::Parser, entries: usize) -> stats::RunStats {
+    stats::RunStats {
+        entries,
+        exit_code: parser.exit_code(),
+        ..parser.stats().clone()
+    }
+}
 
-pub fn run(parser: parser::Parser, mut writer: writer::Writer){
+/// Parses all compilation commands and writes them out, returning what was
+/// found along with the exit code of the build command when the input comes
+/// from one.
+pub fn run(mut parser: parser::Parser, mut writer: writer::Writer) -> stats::RunStats{
     if !parser.parserable() {
-        return;
+        return finish(&parser, 0);
     }
     
+    let is_cmd = parser.is_cmd();
     writer.write("[\n");
-    for items in parser{
-        writer.write(&items[2..items.len() - 2]);
+    let with_headers = parser.config().headers;
+    let verify = parser.config().check.verify;
+    let remap = parser.remap().clone();
+    // 路径映射在写出时最后进行, 头文件推断和检查使用构建环境中的路径
+    let mut units = Vec::new();
+    let mut entries = 0;
+    for items in parser.by_ref(){
+        for mut item in items {
+            if with_headers || verify {
+                units.push(item.clone());
+            }
+            remap.apply(&mut item);
+            writer.write(&item.to_pretty_string(is_cmd));
+            entries += 1;
+        }
+    }
+    if with_headers {
+        for mut item in headers::infer(&units, parser.config().paths) {
+            if parser.entry_filter().matches(&item) {
+                units.push(item.clone());
+                remap.apply(&mut item);
+                writer.write(&item.to_pretty_string(is_cmd));
+                entries += 1;
+            }
+        }
+    }
+    writer.write("\n]");
+    let config = parser.config();
+    if config.clangd.generate || config.system_includes == Some(includes::Mode::QueryDriver) {
+        writer.write_clangd(&writer::Clangd {
+            query_drivers: parser.query_drivers().iter().cloned().collect(),
+            remove: parser.rewriter().removed_flags(),
+            suppress: config.clangd.suppress.clone(),
+        });
+    }
+    stats::RunStats {
+        check: verify.then(|| check::check(&units)),
+        ..finish(&parser, entries)
     }
-    writer.write("\n]")
 }
 
+/// Parses all compilation commands and writes the flag set shared by most
+/// entries into compile_flags.txt, warning when many entries differ or when
+/// options only compile_commands.json supports are set.
+pub fn run_compile_flags(mut parser: parser::Parser, target_dir: Option<&str>) -> stats::RunStats{
+    if !parser.parserable() {
+        return finish(&parser, 0);
+    }
+
+    let config = parser.config();
+    let ignored: Vec<&str> = [
+        (config.headers, "--headers"),
+        (config.clangd.generate, "--clangd"),
+        (config.system_includes == Some(includes::Mode::QueryDriver), "--system-includes query-driver"),
+    ].iter().filter(|(set, _)| *set).map(|(_, name)| *name).collect();
+    if !ignored.is_empty() {
+        eprintln!("Warning: {} ignored when writing compile_flags.txt", ignored.join(", "));
+    }
 
+    let base = env::current_dir().unwrap().join(writer::Writer::target_dir(target_dir));
+    let base = parser::Parser::norm_path(&base.to_string_lossy());
+    let mut counts: HashMap<Vec<String>, (usize, usize)> = HashMap::new();
+    let mut total = 0;
+    let remap = parser.remap().clone();
+    for items in parser.by_ref(){
+        for mut item in items {
+            remap.apply(&mut item);
+            let order = counts.len();
+            counts.entry(item.flags(&base)).or_insert((0, order)).0 += 1;
+            total += 1;
+        }
+    }
+
+    // 出现次数最多的参数集合, 次数相同时取先出现的
+    let (flags, (shared, _)) = counts.into_iter()
+        .max_by(|(_, (a, i)), (_, (b, j))| a.cmp(b).then(j.cmp(i)))
+        .unwrap_or_default();
+    let path = writer::Writer::write_compile_flags(target_dir, &flags);
+    if shared * 10 < total * 9 {
+        eprintln!(
+            "Warning: only {} of {} entries have the flags written to {}, consider compile_commands.json instead",
+            shared, total, path.display()
+        );
+    }
+    finish(&parser, total)
+}
@@ -1,5 +1,7 @@
-use ccjson::{parser, reader::{FileReader, StdinReader}, writer::Writer};
-use clap::Parser;
+use std::{env, process, time::Duration};
+
+use ccjson::{command::{self, CompileCommand}, config::{Check, Config, Format, Paths}, dialect, includes, parser, rewrite, stats, reader::{CommandReader, FileReader, FollowReader, KbuildReader, Reader, StdinReader}, writer::Writer};
+use clap::{Args, Parser, Subcommand};
 
 /// Generate a compilation database for make-based build systems.
 #[derive(Parser, Debug)]
@@ -12,41 +14,536 @@ use clap::Parser;
    | Recommended usage:                                                |
    | 1. sh -x ${build.sh} | ${ccjson} -d ${build_dir}                  |
    | 2. ${ccjson} -p ${build.log} -d ${build_dir}                      |
+   | 3. ${ccjson} -d ${build_dir} -- make -j8                          |
+   | 4. ${ccjson} -n -B -d ${build_dir}                                |
     -------------------------------------------------------------------
-    Check out at https://github.com/qufeiyan/ccjson for more details"
+    Check out at https://github.com/qufeiyan/ccjson for more details",
+    args_conflicts_with_subcommands = true
 )]
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    subcommand: Option<Command>,
+
+    /// Options of `generate`, which runs when no subcommand is given.
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+#[derive(Args, Debug)]
+struct GenerateArgs {
     /// Build log file to parse compilation commands from. (Default: stdin)"
-    #[arg(short, long)]
+    #[arg(short, long, conflicts_with = "build")]
     parse: Option<String>,
 
-    /// Specifies the build path for current project.
-    #[arg(short, long, default_value_t = String::from("./"))]
-    directoy: String,
+    /// Specifies the build path for current project. (Default: ./)
+    #[arg(short, long)]
+    directoy: Option<String>,
 
-    /// Specifies the directory for compile_commands.json.
-    #[arg(short, long, default_value_t = String::from("./"))]
-    output: String,
+    /// Specifies the directory for compile_commands.json. (Default: ./)
+    #[arg(short, long)]
+    output: Option<String>,
 
     /// Specifies the command strings instead of arguments list for the compile_commands.json.
     #[arg(short, long)]
     command: bool,
+
+    /// How paths are written: absolute, relative (to the build path) or
+    /// relative-within-root (relative inside the build path, absolute outside). (Default: relative)
+    #[arg(long, value_parser = ["absolute", "relative", "relative-within-root"])]
+    paths: Option<String>,
+
+    /// Writes compile_flags.txt with the flags most entries share, for small single-config projects.
+    #[arg(long, conflicts_with = "command")]
+    compile_flags: bool,
+
+    /// Scans a Kbuild output tree for .cmd files instead of parsing a build log.
+    #[arg(short, long, conflicts_with_all = ["parse", "build", "dry_run", "follow"])]
+    kbuild_tree: Option<String>,
+
+    /// Format of the build log: make (also `sh -x` scripts), ninja, kbuild, cmake or meson.
+    /// (Default: detected from the first lines of the build log)
+    #[arg(short = 'D', long, value_parser = dialect::NAMES)]
+    dialect: Option<String>,
+
+    /// Number of lines of the build log sampled to detect its format.
+    #[arg(long, default_value_t = 200)]
+    sample_lines: usize,
+
+    /// Prints what ccjson decided, such as the detected log format, on stderr.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Only keeps entries matching a pattern: [file:|directory:|compiler:][re:]<glob or regex>.
+    #[arg(short, long)]
+    include: Vec<String>,
+
+    /// Drops entries matching a pattern, same syntax as --include.
+    #[arg(short = 'x', long)]
+    exclude: Vec<String>,
+
+    /// Adds --target=<triple> to the entries of cross compilers, inferred from their prefix (arm-none-eabi-gcc).
+    #[arg(long)]
+    infer_target: bool,
+
+    /// Adds entries for the headers included by the sources, with the flags of the best-matching source.
+    #[arg(long)]
+    headers: bool,
+
+    /// Makes the built-in include directories of the compilers known to clangd:
+    /// isystem appends them to the entries, query-driver lists the compilers in .clangd.
+    #[arg(long, value_parser = includes::MODES)]
+    system_includes: Option<String>,
+
+    /// Generates .clangd next to compile_commands.json, merged into an existing one.
+    #[arg(long)]
+    clangd: bool,
+
+    /// Rewrites the arguments with a built-in rule set, e.g. gcc-to-clang to drop flags clangd doesn't know.
+    #[arg(long, value_parser = rewrite::PROFILES)]
+    profile: Vec<String>,
+
+    /// Removes a flag from the arguments, matched exactly or by a regex when written re:<regex>.
+    #[arg(long, allow_hyphen_values = true)]
+    remove_flag: Vec<String>,
+
+    /// Appends a flag to the arguments.
+    #[arg(long, allow_hyphen_values = true)]
+    append_flag: Vec<String>,
+
+    /// Resolves symlinks through the filesystem instead of lexically, for the paths which exist.
+    #[arg(long)]
+    resolve_symlinks: bool,
+
+    /// Keeps a symlinked build path as seen from the shell when resolving symlinks.
+    #[arg(long, requires = "resolve_symlinks")]
+    keep_root: bool,
+
+    /// Maps the path prefix FROM to TO in every path, as in /workspace=/home/alice/proj.
+    /// May be repeated, the longest matching FROM wins.
+    #[arg(long, value_name = "FROM=TO")]
+    remap: Vec<String>,
+
+    /// Writes the statistics of the run as json to a file.
+    #[arg(long, value_name = "FILE")]
+    stats_json: Option<String>,
+
+    /// Doesn't print the summary of the run on stderr.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Checks the generated database against the filesystem, as `ccjson check` does.
+    #[arg(long)]
+    verify: bool,
+
+    #[command(flatten)]
+    limits: CheckArgs,
+
+    /// Keeps reading the build log as it grows, updating compile_commands.json on the fly.
+    #[arg(short, long, requires = "parse")]
+    follow: bool,
+
+    /// Stops following after no data is appended for so many seconds.
+    #[arg(long, default_value_t = 60, requires = "follow")]
+    timeout: u64,
+
+    /// Stops following once a line of the build log matches this regex.
+    #[arg(long, requires = "follow")]
+    until: Option<String>,
+
+    /// Saves the output of the build command into a log file as well.
+    #[arg(short, long, conflicts_with = "parse")]
+    log: Option<String>,
+
+    /// Traces the build command to capture every compiler it runs. (Linux only)
+    #[arg(short, long, requires = "build", conflicts_with = "log")]
+    trace: bool,
+
+    /// Runs `make -nwk` to print the build commands without building anything.
+    #[arg(short = 'n', long, conflicts_with_all = ["parse", "trace"])]
+    dry_run: bool,
+
+    /// Passes `-B` to make in dry-run mode, for projects which are already built.
+    #[arg(short = 'B', long, requires = "dry_run")]
+    always_make: bool,
+
+    /// Build command to run, its output is parsed while building.
+    /// In dry-run mode, extra arguments for make.
+    #[arg(last = true)]
+    build: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct CheckArgs {
+    /// Fails the check when more entries have a missing file. (Default: 0)
+    #[arg(long)]
+    max_missing_files: Option<usize>,
+
+    /// Fails the check when more entries have a missing directory. (Default: 0)
+    #[arg(long)]
+    max_missing_directories: Option<usize>,
+
+    /// Fails the check when more include directories are missing. (Default: no limit)
+    #[arg(long)]
+    max_missing_includes: Option<usize>,
+}
+
+impl CheckArgs {
+    fn apply(&self, check: &mut Check) {
+        if let Some(m) = self.max_missing_files {
+            check.max_missing_files = m;
+        }
+        if let Some(m) = self.max_missing_directories {
+            check.max_missing_directories = m;
+        }
+        if self.max_missing_includes.is_some() {
+            check.max_missing_includes = self.max_missing_includes;
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generates compile_commands.json from a build, the default.
+    Generate(Box<GenerateArgs>),
+    /// Merges databases, entries of later ones replacing those of earlier ones for the same file.
+    Merge {
+        /// Databases to merge.
+        #[arg(required = true)]
+        databases: Vec<String>,
+        /// Database to write.
+        #[arg(short, long, default_value = "compile_commands.json")]
+        output: String,
+    },
+    /// Inspects the configuration read from .ccjson.toml and $XDG_CONFIG_HOME/ccjson/config.toml.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Checks that the directories, files and include directories of a database exist.
+    Check {
+        /// Database to check.
+        #[arg(default_value = "compile_commands.json")]
+        database: String,
+        #[command(flatten)]
+        limits: CheckArgs,
+    },
+    /// Keeps the entries of a database matching the include and exclude patterns.
+    Filter {
+        /// Database to filter.
+        database: String,
+        /// Only keeps entries matching a pattern: [file:|directory:|compiler:][re:]<glob or regex>.
+        #[arg(short, long)]
+        include: Vec<String>,
+        /// Drops entries matching a pattern, same syntax as --include.
+        #[arg(short = 'x', long)]
+        exclude: Vec<String>,
+        /// Writes the result there instead of rewriting the database in place.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Prints the number of entries, files, directories and compilers of a database.
+    Stats {
+        /// Database to summarise.
+        #[arg(default_value = "compile_commands.json")]
+        database: String,
+        /// Prints the statistics as json.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compares two databases, exiting with 1 when they differ.
+    Diff {
+        old: String,
+        new: String,
+        /// Prints the differences as json.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Applies prefix maps to an existing compile_commands.json.
+    Remap {
+        /// Database to rewrite.
+        database: String,
+        /// Maps the path prefix FROM to TO, may be repeated.
+        #[arg(long, value_name = "FROM=TO", required = true)]
+        remap: Vec<String>,
+        /// Writes the result there instead of rewriting the database in place.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Prints the effective configuration, with the flags of `generate` given after it applied.
+    Show(Box<GenerateArgs>),
+}
+
+impl GenerateArgs {
+    /// CLI flags override the config files.
+    fn apply(&self, config: &mut Config) {
+        if self.directoy.is_some() {
+            config.directory.clone_from(&self.directoy);
+        }
+        if self.output.is_some() {
+            config.output.clone_from(&self.output);
+        }
+        if self.command {
+            config.format = Format::Command;
+        }
+        match self.paths.as_deref() {
+            Some("absolute") => config.paths = Paths::Absolute,
+            Some("relative") => config.paths = Paths::Relative,
+            Some("relative-within-root") => config.paths = Paths::RelativeWithinRoot,
+            _ => {}
+        }
+        if self.compile_flags {
+            config.format = Format::CompileFlags;
+        }
+        if self.dialect.is_some() {
+            config.dialect.clone_from(&self.dialect);
+        }
+        if self.resolve_symlinks {
+            config.resolve_symlinks = true;
+        }
+        if self.keep_root {
+            config.keep_root = true;
+        }
+        if self.verify {
+            config.check.verify = true;
+        }
+        self.limits.apply(&mut config.check);
+        config.remap.extend(self.remap.iter().cloned());
+        config.include.extend(self.include.iter().cloned());
+        config.exclude.extend(self.exclude.iter().cloned());
+        match self.system_includes.as_deref() {
+            Some("isystem") => config.system_includes = Some(includes::Mode::Isystem),
+            Some("query-driver") => config.system_includes = Some(includes::Mode::QueryDriver),
+            _ => {}
+        }
+        if self.headers {
+            config.headers = true;
+        }
+        if self.clangd {
+            config.clangd.generate = true;
+        }
+        if self.infer_target {
+            config.target.inject = true;
+        }
+        config.rewrite.profiles.extend(self.profile.iter().cloned());
+        config.rewrite.remove.extend(self.remove_flag.iter().cloned());
+        config.rewrite.append.extend(self.append_flag.iter().cloned());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn trace_reader(build: &[String]) -> Box<dyn Reader> {
+    Box::new(exit_on_error(ccjson::tracer::TraceReader::new(build)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn trace_reader(_build: &[String]) -> Box<dyn Reader> {
+    panic!("tracing is only supported on linux");
+}
+
+/// Checks a database and prints the report, returning whether it passed.
+fn check_database(database: &str, limits: &Check) -> bool {
+    let entries = read_database(database);
+    let report = ccjson::check::check(&entries);
+    report.print();
+    !report.exceeds(limits)
+}
+
+/// Reads a database, exiting on errors.
+fn read_database(database: &str) -> Vec<CompileCommand> {
+    command::read_database(database).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(2);
+    })
+}
+
+fn write_database(database: &str, entries: &[CompileCommand], config: &Config) {
+    if let Err(e) = command::write_database(database, entries, config.format == Format::Command) {
+        eprintln!("Error: {}", e);
+        process::exit(2);
+    }
+}
+
+/// Exits on invalid config values.
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(2);
+    })
 }
 
 fn main() {
-    let args = Args::parse();
+    let cli = Cli::parse();
+
+    let mut config = exit_on_error(Config::load(&env::current_dir().unwrap()));
 
-    let parser: parser::Parser = match args.parse {
+    match cli.subcommand {
+        Some(Command::Config { action: ConfigAction::Show(args) }) => {
+            args.apply(&mut config);
+            print!("{}", config.to_toml());
+        }
+        Some(Command::Generate(args)) => {
+            args.apply(&mut config);
+            generate(*args, config);
+        }
+        Some(Command::Merge { databases, output }) => {
+            let databases: Vec<Vec<CompileCommand>> = databases.iter().map(|d| read_database(d)).collect();
+            write_database(&output, &command::merge(databases), &config);
+        }
+        Some(Command::Check { database, limits }) => {
+            limits.apply(&mut config.check);
+            if !check_database(&database, &config.check) {
+                process::exit(1);
+            }
+        }
+        Some(Command::Filter { database, include, exclude, output }) => {
+            config.include.extend(include);
+            config.exclude.extend(exclude);
+            let filter = exit_on_error(config.filter());
+            let mut entries = read_database(&database);
+            entries.retain(|e| filter.matches(e));
+            write_database(output.as_ref().unwrap_or(&database), &entries, &config);
+        }
+        Some(Command::Stats { database, json }) => {
+            let stats = stats::DatabaseStats::new(&read_database(&database));
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&stats).unwrap()),
+                false => print!("{}", stats),
+            }
+        }
+        Some(Command::Diff { old, new, json }) => {
+            let diff = ccjson::diff::diff(&read_database(&old), &read_database(&new));
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&diff).unwrap()),
+                false => print!("{}", diff),
+            }
+            if !diff.is_empty() {
+                process::exit(1);
+            }
+        }
+        Some(Command::Remap { database, remap, output }) => {
+            config.remap.extend(remap);
+            let remap = exit_on_error(config.remap());
+            let output = output.as_ref().unwrap_or(&database);
+            exit_on_error(ccjson::remap::remap_database(&database, output, &remap).map(|_| ()));
+        }
+        None => {
+            cli.generate.apply(&mut config);
+            generate(cli.generate, config);
+        }
+    }
+}
+
+/// Generates the database from a build, with the config the arguments are
+/// applied to.
+fn generate(args: GenerateArgs, config: Config) {
+    let remap = exit_on_error(config.remap());
+    let filter = exit_on_error(config.filter());
+    let rewriter = exit_on_error(config.rewriter());
+
+    let directory = config.directory.clone().unwrap_or_else(|| String::from("./"));
+    let output = config.output.clone().unwrap_or_else(|| String::from("./"));
+    let is_cmd = config.format == Format::Command;
+    let is_flags = config.format == Format::CompileFlags;
+    let mut dialect = config.dialect.clone();
+    let mut parser: parser::Parser = match args.parse {
+        Some(p) if args.follow => {
+            let until = args.until.map(|u| {
+                exit_on_error(regex::Regex::new(&u).map_err(|e| format!("invalid --until regex {}: {}", u, e)))
+            });
+            let file = FollowReader::new(&p, Duration::from_secs(args.timeout), until);
+            ccjson::parser::Parser::new(Box::new(file), Some(directory), is_cmd)
+        },
         Some(p) => {
             let file: FileReader = FileReader::new(&p);
-            ccjson::parser::Parser::new(Box::new(file), Some(args.directoy), args.command)
+            ccjson::parser::Parser::new(Box::new(file), Some(directory), is_cmd)
+        },
+        None if args.kbuild_tree.is_some() => {
+            // Kbuild 在输出目录的顶层执行所有编译命令
+            let tree = args.kbuild_tree.unwrap();
+            dialect = Some("kbuild".to_string());
+            ccjson::parser::Parser::new(Box::new(KbuildReader::new(&tree)), Some(tree), is_cmd)
+        },
+        None if args.dry_run => {
+            let flags = if args.always_make { "-Bnwk" } else { "-nwk" };
+            let mut make = vec!["make".to_string(), flags.to_string()];
+            make.extend(args.build);
+            let build = exit_on_error(CommandReader::new(&make, args.log.as_ref()));
+            ccjson::parser::Parser::new(Box::new(build), Some(directory), is_cmd)
+        },
+        None if args.trace => {
+            ccjson::parser::Parser::new(trace_reader(&args.build), Some(directory), is_cmd)
+        },
+        None if !args.build.is_empty() => {
+            let build = exit_on_error(CommandReader::new(&args.build, args.log.as_ref()));
+            ccjson::parser::Parser::new(Box::new(build), Some(directory), is_cmd)
         },
         None => {
-            ccjson::parser::Parser::new(Box::new(StdinReader::new()), Some(args.directoy), args.command)
+            ccjson::parser::Parser::new(Box::new(StdinReader::new()), Some(directory), is_cmd)
         }
     };
 
-    let writer = Writer::new(Some(&args.output), 256);
-    ccjson::run(parser, writer);
+    let check = config.check.clone();
+    parser.set_config(config);
+    parser.set_filter(filter);
+    parser.set_rewriter(rewriter);
+    parser.set_remap(remap);
+    match dialect {
+        Some(d) => {
+            if d != "make" {
+                parser.add_dialect(dialect::by_name(&d).unwrap_or_else(|| {
+                    eprintln!("Error: unknown dialect {}, expected one of {}", d, dialect::NAMES.join(", "));
+                    process::exit(2);
+                }));
+            }
+            if args.verbose {
+                eprintln!("ccjson: dialects: {}", parser.dialects().join(", "));
+            }
+        }
+        // 识别需要预读日志, 跟随模式下会一直等到足够的行写入才开始输出
+        None if args.follow => {
+            if args.verbose {
+                eprintln!("ccjson: dialects: {} (not detected in follow mode, set one with --dialect)", parser.dialects().join(", "));
+            }
+        }
+        None => {
+            parser.detect_dialects(args.sample_lines);
+            if args.verbose {
+                eprintln!(
+                    "ccjson: dialects: {} (detected from the first {} lines, override with --dialect)",
+                    parser.dialects().join(", "), args.sample_lines
+                );
+            }
+        }
+    }
+
+    let stats = match is_flags {
+        true => ccjson::run_compile_flags(parser, Some(&output)),
+        false => {
+            let mut writer = Writer::new(Some(&output), 256);
+            writer.set_incremental(args.follow);
+            ccjson::run(parser, writer)
+        }
+    };
+    if !args.quiet {
+        eprint!("{}", stats);
+    }
+    if let Some(file) = &args.stats_json {
+        let json = serde_json::to_string_pretty(&stats).unwrap();
+        exit_on_error(std::fs::write(file, json).map_err(|e| format!("can't write {}: {}", file, e)));
+    }
 
+    let code = stats.exit_code;
+    if let Some(report) = &stats.check {
+        report.print();
+        if report.exceeds(&check) && code.unwrap_or(0) == 0 {
+            process::exit(1);
+        }
+    }
+    if let Some(code) = code {
+        process::exit(code);
+    }
 }
\ No newline at end of file
@@ -1,12 +1,43 @@
-use std::{env, fs, io::{self, Write}, path::{self, Path, PathBuf}};
-use crate::reader::Reader;
-use serde_json::{Map, Value};
+use std::{cell::RefCell, collections::{BTreeSet, HashMap, HashSet, VecDeque}, env, fs, io::{self, Write}, path::{self, Path}};
+use crate::{command::{split_command, CompileCommand}, config::{Config, Paths}, dialect::{self, Action, Dialect, State}, filter::Filter, includes::{self, Mode}, reader::{Reader, Record}, remap::Remap, rewrite::Rewriter, stats::RunStats, target};
+
+macro_rules! find_target {
+    ($s:expr, $($t:expr), *) => {{
+        $($s.ends_with($t)) || *
+    }};
+}
+
+fn is_object(s: &str) -> bool {
+    find_target!(s, ".o", ".obj")
+}
 
 pub struct Parser{
     reader: Box<dyn Reader>,
     build_dir: String,
     directory: String,
+    // make 嵌套进入目录时保存的上一级目录
+    directories: Vec<String>,
     is_cmd: bool,
+    // 按顺序尝试的日志方言, 最后一个总是 make
+    dialects: Vec<Box<dyn Dialect>>,
+    // 自动识别方言时预读的输入
+    pending: VecDeque<Record>,
+    config: Config,
+    filter: Filter,
+    rewriter: Rewriter,
+    remap: Remap,
+    /// Paths resolved through the filesystem, `None` for missing ones.
+    resolved: RefCell<HashMap<String, Option<String>>>,
+    /// Real path of the build directory and the path it is shown as, when
+    /// symlinked roots are kept.
+    root_map: Option<(String, String)>,
+    /// Logical working directory of the shell, `$PWD` when ccjson starts.
+    pwd: Option<String>,
+    stats: RunStats,
+    // 已经输出过的 libtool 编译的源文件
+    libtool_files: HashSet<(String, String, String)>,
+    includes: includes::Cache,
+    query_drivers: BTreeSet<String>,
 }
 
 impl Parser{
@@ -27,67 +58,273 @@ impl Parser{
             reader,
             build_dir,
             directory, 
-            is_cmd
+            directories: Vec::new(),
+            is_cmd,
+            dialects: vec![Box::new(dialect::Make::new())],
+            pending: VecDeque::new(),
+            config: Config::default(),
+            filter: Filter::new(),
+            rewriter: Rewriter::new(),
+            remap: Remap::new(),
+            resolved: RefCell::new(HashMap::new()),
+            root_map: None,
+            pwd: env::var("PWD").ok(),
+            stats: RunStats::default(),
+            libtool_files: HashSet::new(),
+            includes: includes::Cache::new(),
+            query_drivers: BTreeSet::new(),
         }
     }
 
-    pub fn parserable(&self) -> bool{
-        self.reader.readable()
-    }
-
-    fn parse_directory(&mut self, str: &String) -> Option<bool>{
-        let get_directory = |str: &String| -> String{
-            let strs = str.split_whitespace();
-            let res: Vec<&str> = strs.into_iter()
-                .filter(|s| s.contains("'/") || s.starts_with('/'))
-                .collect();
-            assert_eq!(res.len(), 1);
-            match res[0].starts_with('\'') {
-                true => {
-                    let len = res[0].len();
-                    res[0][1..len-1].to_string()
+    /// Whether entries are written with a `command` string instead of `arguments`.
+    pub fn is_cmd(&self) -> bool{
+        self.is_cmd
+    }
+
+    /// What was read and found so far, entries written and the exit code
+    /// being left to the caller.
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    fn skip(&mut self, reason: &str) {
+        *self.stats.skipped.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn entry_filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn rewriter(&self) -> &Rewriter {
+        &self.rewriter
+    }
+
+    pub fn remap(&self) -> &Remap {
+        &self.remap
+    }
+
+    /// `--query-driver` globs of the compilers seen so far, collected in
+    /// `query-driver` mode.
+    pub fn query_drivers(&self) -> &BTreeSet<String> {
+        &self.query_drivers
+    }
+
+    /// Sets the compilers, wrappers and source extensions to recognise.
+    pub fn set_config(&mut self, config: Config){
+        self.config = config;
+        self.root_map = None;
+        if !self.config.resolve_symlinks {
+            return;
+        }
+
+        let real = match fs::canonicalize(&self.build_dir) {
+            Ok(p) => p.to_string_lossy().into_owned(),
+            Err(_) => return,
+        };
+        let root = match self.config.keep_root {
+            true => self.pwd.as_deref()
+                .and_then(|pwd| Parser::visible_path(&real, pwd))
+                .unwrap_or_else(|| self.build_dir.clone()),
+            false => real.clone(),
+        };
+        if self.directory == self.build_dir {
+            self.directory.clone_from(&root);
+        }
+        self.build_dir.clone_from(&root);
+        if root != real {
+            self.root_map = Some((real, root));
+        }
+    }
+
+    /// The path as seen from `pwd`, which keeps the symlinks the user went
+    /// through, when it is below it.
+    fn visible_path(real: &str, pwd: &str) -> Option<String> {
+        let real_pwd = fs::canonicalize(pwd).ok()?;
+        let rest = Path::new(real).strip_prefix(&real_pwd).ok()?;
+        Some(Parser::norm_path(&Path::new(pwd).join(rest).to_string_lossy()))
+    }
+
+    /// Resolves the symlinks of an existing path, caching the result.
+    fn resolve(&self, path: &str) -> Option<String> {
+        self.resolved.borrow_mut()
+            .entry(path.to_string())
+            .or_insert_with(|| fs::canonicalize(path).ok().map(|p| p.to_string_lossy().into_owned()))
+            .clone()
+    }
+
+    /// Only entries accepted by the filter are produced.
+    pub fn set_filter(&mut self, filter: Filter){
+        self.filter = filter;
+    }
+
+    /// Rewrites the arguments of every entry.
+    pub fn set_rewriter(&mut self, rewriter: Rewriter){
+        self.rewriter = rewriter;
+    }
+
+    /// Maps the paths of every entry when it is written, after headers are
+    /// inferred and the entries are checked in the build's own paths.
+    pub fn set_remap(&mut self, remap: Remap){
+        self.remap = remap;
+    }
+
+    /// Adds a dialect, which is tried before the ones added earlier. The
+    /// built-in make dialect is always tried last.
+    pub fn add_dialect(&mut self, dialect: Box<dyn Dialect>){
+        self.dialects.insert(0, dialect);
+    }
+
+    /// Names of the dialects in the order they are tried.
+    pub fn dialects(&self) -> Vec<&str>{
+        self.dialects.iter().map(|d| d.name()).collect()
+    }
+
+    /// Reads ahead up to `lines` lines of build log and adds the built-in
+    /// dialects detected from them, returning their names. The lines read
+    /// ahead are parsed as usual afterwards.
+    pub fn detect_dialects(&mut self, lines: usize) -> Vec<String>{
+        let mut sample: Vec<String> = Vec::new();
+        while sample.len() < lines && self.reader.readable() {
+            match self.reader.read_record() {
+                Some(record) => {
+                    if let Record::Line(line) = &record {
+                        sample.push(line.clone());
+                    }
+                    self.pending.push_back(record);
                 }
-                false => res[0].to_string()
+                None => break,
             }
+        }
+
+        let detected = dialect::detect(&sample);
+        let names = detected.iter().map(|d| d.name().to_string()).collect();
+        for d in detected {
+            self.add_dialect(d);
+        }
+        names
+    }
+
+    pub fn parserable(&self) -> bool{
+        !self.pending.is_empty() || self.reader.readable()
+    }
+
+    fn read_record(&mut self) -> Option<Record>{
+        let record = match self.pending.pop_front() {
+            Some(record) => Some(record),
+            None => self.reader.read_record(),
         };
-        if str.contains("Make[1]") && str.contains("Entering directory") || str.contains("+ cd"){
-            self.directory = get_directory(str).to_string();
-            Some(true)
-        }else if str.contains("Make[1]") && str.contains("Leaving directory"){
-            let res: String = get_directory(str);
-            assert_eq!(res, self.directory);
-            self.directory.clear();
-            Some(false)
-        }else {
-            // println!("Error: {}:{} something goes wrong in \"{}\"", file!(), line!(), str);
-            None
+        if record.is_some() {
+            self.stats.lines += 1;
         }
-    }  
+        record
+    }
+
+    /// Exit code of the build command being parsed, if any.
+    pub fn exit_code(&self) -> Option<i32>{
+        self.reader.exit_code()
+    }
 
-    pub fn parse_line(&mut self) -> Option<String>{
-        let line = match self.reader.read_line() {
-            Some(line) => line,
-            None => return None,
+    pub fn parse_line(&mut self) -> Option<Vec<CompileCommand>>{
+        let mut line = match self.read_record()? {
+            Record::Line(line) => line,
+            Record::Exec { directory, arguments } => return self.parse_exec(&directory, &arguments),
         };
-        
-        let res = self.parse_directory(&line);
-        match res {
-            Some(_) => None,
-            None => {
-                self.parse_warning_or_error(&line);
-                let res = self.parser_command(&line)?;
-                Some(res)
+
+        // 以 '\' 结尾的行与下一行组成一个逻辑行
+        while line.ends_with("\\\n") && self.parserable() {
+            match self.read_record() {
+                Some(Record::Line(next)) => {
+                    line.truncate(line.len() - 2);
+                    line.push(' ');
+                    line.push_str(&next);
+                }
+                _ => break,
             }
         }
+
+        self.parse_log_line(&line)
     }
-    
+
+    /// Hands a logical line to the dialects and applies what they make of it.
+    fn parse_log_line(&mut self, line: &str) -> Option<Vec<CompileCommand>>{
+        let state = State {
+            config: &self.config,
+            build_dir: &self.build_dir,
+            directory: &self.directory,
+            directories: &self.directories,
+        };
+        let action = self.dialects.iter_mut()
+            .find_map(|d| d.parse(line, &state))
+            .unwrap_or_else(|| Action::Command { directory: None, command: line.to_string() });
+
+        if !matches!(action, Action::Command { .. }) {
+            self.skip(match action {
+                Action::Skip => "dialect",
+                _ => "directory",
+            });
+        }
+        match action {
+            Action::Skip => None,
+            Action::Enter(dir) => {
+                let dir = self.absolute_path(&dir);
+                self.directories.push(std::mem::replace(&mut self.directory, dir));
+                None
+            }
+            Action::Leave => {
+                self.directory = self.directories.pop().unwrap_or_else(|| self.build_dir.clone());
+                None
+            }
+            Action::Cd(dir) => {
+                self.directory = self.absolute_path(&dir);
+                None
+            }
+            Action::Command { directory: None, command } => {
+                self.parse_warning_or_error(line);
+                self.parser_command(&command)
+            }
+            Action::Command { directory: Some(dir), command } => {
+                self.parse_warning_or_error(line);
+                // 只对这一条命令生效
+                let dir = self.absolute_path(&dir);
+                let saved = std::mem::replace(&mut self.directory, dir);
+                let res = self.parser_command(&command);
+                self.directory = saved;
+                res
+            }
+        }
+    }
+
+    /// Parses a traced compiler execution, whose arguments are exact and whose
+    /// working directory is known, so no directory tracking is needed.
+    fn parse_exec(&mut self, directory: &str, arguments: &[String]) -> Option<Vec<CompileCommand>> {
+        // 只接受编译器本身的调用, 忽略 make、sh 以及 clang -cc1 等内部调用
+        let cc = arguments.first()?;
+        if !self.config.is_compiler(cc) || arguments.iter().any(|s| s == "-cc1") {
+            self.skip("not-compiler");
+            return None;
+        }
+
+        directory.clone_into(&mut self.directory);
+        let tokens: Vec<&str> = arguments.iter().map(|s| s.as_str()).collect();
+        self.compile_commands(&tokens)
+    }
+
     /// Parses a single line of GCC output to identify and print warnings or errors.
     ///
     /// # Arguments
     ///
     /// * `line` - A string slice that holds a single line of GCC compiler output.
     /// 
-    fn parse_warning_or_error(&self, line: &String) {
+    #[allow(clippy::single_match)]
+    fn parse_warning_or_error(&self, line: &str) {
+        // 构建命令的输出已经原样打印过, 不再重复打印其中的警告和错误
+        if self.reader.echoes() {
+            return;
+        }
         let pattern = regex::Regex::new(r"^(.*?):(\d+):(\d+):\s+(error|warning):\s+(.+)\n$").unwrap();
         let caps = pattern.captures(line);
 
@@ -129,117 +366,168 @@ impl Parser{
     /// let res = self.parser_command(&parser);
     /// 
     /// ``` 
-    fn parser_command(&mut self, line_str: &str) -> Option<String> {
-        let mut iter = line_str.split_whitespace();
+    fn parser_command(&mut self, line_str: &str) -> Option<Vec<CompileCommand>> {
+        // 按 shell 的规则去掉引号, 与跟踪得到的参数一致
+        let args = split_command(line_str);
+        let tokens: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        self.compile_commands(&tokens)
+    }
+
+    /// Builds the compilation database entries from the tokens of a compiler
+    /// invocation, one entry for each source file.
+    fn compile_commands(&mut self, tokens: &[&str]) -> Option<Vec<CompileCommand>> {
+        let libtool_tokens = self.unwrap_libtool(tokens);
+        let libtool = libtool_tokens.is_some();
+        let tokens = libtool_tokens.as_deref().unwrap_or(tokens);
+
+        let mut iter = tokens.iter().copied();
         let mut iter_copy = iter.clone();
-        macro_rules! find_target {
-            ($s:expr, $($t:expr), *) => {{
-                $($s.ends_with($t)) || *
-            }};
-        }
-        let find_cc = |s: &str| -> bool {
-            find_target!(s, "gcc", "g++", "clang", "clang++")
-        };
-        let find_src = |s: &str| -> bool {
-            find_target!(s, ".c", ".cc", ".cpp", ".cxx")
-        };
-        let find_obj = |s: &str| -> bool {
-            find_target!(s, ".o", ".obj")
-        };
-        
-        let res_cc = iter.find(|s| find_cc(s) );
-        let cc = match res_cc {
-            Some(cc) => cc,
-            None => return  None
+
+        let Some(cc) = iter.find(|s| self.config.is_compiler(s) ) else {
+            self.skip("not-command");
+            return None;
         };
 
-        let files: Vec<&str> = iter.filter(|s| find_src(s) ).collect();
+        let files: Vec<&str> = iter.filter(|s| self.config.is_source(s) ).collect();
         if files.is_empty(){
+            self.skip("no-source");
             return None;
         }
+        self.stats.commands += 1;
+        self.stats.compilers.insert(cc.to_string());
+        self.stats.directories.insert(self.directory.clone());
 
-        let mut args:Vec<Value> = Vec::new();
+        let mut args: Vec<String> = Vec::new();
         while let Some(s) = iter_copy.next() {
             if s.starts_with("-I") {
                 let target = match s.eq("-I") {
-                    true => s.to_owned() + &Parser::relative_path(
+                    true => s.to_owned() + &Parser::rebase(
                         &self.absolute_path(iter_copy.next()?),
-                        &self.build_dir
+                        &self.build_dir,
+                        self.config.paths
                     ), 
-                    false => s.split_at(2).0.to_owned() + &Parser::relative_path(
+                    false => s.split_at(2).0.to_owned() + &Parser::rebase(
                         &self.absolute_path(s.split_at(2).1), 
-                        &self.build_dir
+                        &self.build_dir,
+                        self.config.paths
                     ) 
                 };
-                args.push(Value::String(target));
+                args.push(target);
             }else if s.starts_with("-D") {
                 let target = match s.eq("-D") {
                     true => s.to_owned() + iter_copy.next()?,
                     false => s.to_string()
                 };
-                args.push(Value::String(target)); 
-            }else if find_obj(s) {
+                args.push(target); 
+            }else if is_object(s) {
                 let abs_file = self.absolute_path(s);
-                args.push(Value::String(Parser::relative_path(
+                args.push(Parser::rebase(
                     &abs_file,
-                    &self.build_dir
-                )));
-            }else if !find_src(s) && !find_cc(s) {
-                args.push(Value::String(s.to_string()));
+                    &self.build_dir,
+                    self.config.paths
+                ));
+            }else if !self.config.is_source(s) && !self.config.is_compiler(s) && !self.config.is_wrapper(s) {
+                args.push(s.to_string());
             }
         }
 
-        let mut map = Map::new(); 
-       
         // directory: "~/..."
         if self.directory.is_empty(){
             self.directory.clone_from(&self.build_dir);
         }
-        map.insert("directory".to_string(), Value::String(self.build_dir.clone()));
 
         // arguments: "-I... -D..."
-        args.insert(0, Value::String(cc.to_string()));
+        args.insert(0, cc.to_string());
+        if self.config.target.inject {
+            target::inject(&mut args, &self.config);
+        }
+        self.rewriter.apply(&mut args);
 
         // file: "*.c" 
-        let items: Vec<Map<String, Value>> = files.iter().map(|s|{
-            let mut map = map.clone();
+        let items: Vec<CompileCommand> = files.iter().map(|s|{
             let abs_file = self.absolute_path(s);
-            let file_val = Parser::relative_path(
-                // s,
+            let file_val = Parser::rebase(
                 &abs_file,
-                &self.build_dir 
+                &self.build_dir,
+                self.config.paths
             );
-
-            if !self.is_cmd {
-                let mut args_copy = args.clone();
-                args_copy.push(Value::String(file_val.clone()));
-                let value_args = Value::Array(args_copy);
-                map.insert("arguments".to_string(), value_args);
-            }else {
-                let cmd_vec = args.iter().map(
-                    |s| s.as_str().expect("arguments should be string")
-                ).collect::<Vec<&str>>();
-                let cmd = cmd_vec.join(" ");
-                map.insert("command".to_string(), Value::String(cmd));
-                map.insert("output".to_string(), Value::String(
-                    PathBuf::from(&file_val)
-                    .with_extension("o")
-                    .to_str()
-                    .expect("output file should be string")
-                    .to_string()
-                ));
-            } 
-            
-            map.insert("file".to_string(), Value::String(file_val));
-            // println!("{:#?}", map);  
-            map
+            CompileCommand::new(&self.build_dir, &file_val, args.clone())
         }).collect();
 
-        let s = serde_json::to_string_pretty(&items).unwrap();
-        Some(s)      
+        // libtool 会分别以 PIC 和非 PIC 方式编译同一个源文件, 只保留第一次;
+        // 以目录、源文件和目标文件名(去掉扩展名)区分, 同一源文件编进不同库时各自保留
+        let items: Vec<CompileCommand> = match libtool {
+            true => {
+                let stem = Parser::object_stem(&args);
+                items.into_iter()
+                    .filter(|item| self.libtool_files.insert(
+                        (self.directory.clone(), item.file.clone(), stem.clone())
+                    ))
+                    .collect()
+            }
+            false => items,
+        };
+        let found = files.len();
+        let mut items: Vec<CompileCommand> = items.into_iter()
+            .filter(|item| self.filter.matches(item))
+            .collect();
+        self.stats.dropped += found - items.len();
+        if items.is_empty() {
+            return None;
+        }
+
+        // 相对路径的编译器按命令执行的目录解析, 而不是 ccjson 自身的工作目录
+        let compiler = match self.config.system_includes {
+            Some(_) => includes::resolve(cc, &self.directory),
+            None => String::new(),
+        };
+        match self.config.system_includes {
+            Some(Mode::Isystem) => {
+                for item in items.iter_mut() {
+                    let dirs = self.includes.get(&compiler, includes::language(&item.file));
+                    for dir in dirs {
+                        item.arguments.push("-isystem".to_string());
+                        item.arguments.push(dir.clone());
+                    }
+                }
+            }
+            Some(Mode::QueryDriver) => {
+                self.query_drivers.insert(includes::query_driver_glob(&compiler, &self.directory, &self.config));
+            }
+            None => {}
+        }
+
+        Some(items)
+    }
+
+    /// Unwraps a libtool invocation, either `libtool --tag=CC --mode=compile gcc ...`
+    /// or the `libtool: compile:  gcc ... >/dev/null 2>&1` lines it prints,
+    /// into the compiler command it runs. Returns `None` for other commands.
+    fn unwrap_libtool<'a>(&self, tokens: &[&'a str]) -> Option<Vec<&'a str>> {
+        let libtool = tokens.iter().position(|s| {
+            s.rsplit('/').next() == Some("libtool") || *s == "libtool:"
+        })?;
+        let cc = libtool + tokens[libtool..].iter().position(|s| self.config.is_compiler(s))?;
+
+        // 去掉 libtool 自身的参数以及 shell 重定向
+        let redirect = regex::Regex::new(r"^\d*>").unwrap();
+        Some(tokens[cc..].iter()
+            .copied()
+            .filter(|s| !redirect.is_match(s))
+            .collect())
+    }
+
+    /// `-o` 指定的目标文件名, 去掉目录和扩展名: `.libs/foo.o` -> `foo`
+    fn object_stem(args: &[String]) -> String {
+        let output = args.iter().enumerate().find_map(|(i, s)| match s.as_str() {
+            "-o" => args.get(i + 1).map(|s| s.as_str()),
+            s => s.strip_prefix("-o"),
+        }).unwrap_or_default();
+        let name = Path::new(output).file_name().and_then(|s| s.to_str()).unwrap_or_default();
+        name.split('.').next().unwrap_or_default().to_string()
     }
 
-    fn norm_path(path: &str) -> String{
+    pub(crate) fn norm_path(path: &str) -> String{
         let path_items: Vec<_> = path.split('/').collect();
         let initial_slashs: usize = match path.starts_with('/') {
             true =>{
@@ -250,7 +538,7 @@ impl Parser{
 
         let mut new_path_items: Vec<&str> = Vec::new();       
         for item in path_items{
-            if ["", "."].iter().any(|s| *s == item){ continue; }
+            if ["", "."].contains(&item){ continue; }
             
             let lens = new_path_items.len(); 
             if item != ".." 
@@ -276,6 +564,26 @@ impl Parser{
     }
 
     fn absolute_path(&self, src_path: &str) -> String{
+        if !self.config.resolve_symlinks {
+            return self.lexical_path(src_path);
+        }
+
+        // 先经过符号链接再处理 "..", 与文件系统的行为一致
+        let joined = match src_path.starts_with('/') {
+            true => src_path.to_string(),
+            false => format!("{}/{}", self.directory, src_path),
+        };
+        let path = self.resolve(&joined).unwrap_or_else(|| self.lexical_path(src_path));
+        match &self.root_map {
+            Some((real, root)) => match Path::new(&path).strip_prefix(real) {
+                Ok(rest) => Parser::norm_path(&Path::new(root).join(rest).to_string_lossy()),
+                Err(_) => path,
+            },
+            None => path,
+        }
+    }
+
+    fn lexical_path(&self, src_path: &str) -> String{
         let norm_src_path = Parser::norm_path(src_path);
         
         if norm_src_path.starts_with('/'){
@@ -292,7 +600,20 @@ impl Parser{
         }
     }
 
-    fn relative_path(src_path: &str, base_path: &str) -> String{
+    /// Writes an absolute path in the form `paths` asks for, relative ones
+    /// being relative to `root`.
+    pub(crate) fn rebase(abs_path: &str, root: &str, paths: Paths) -> String{
+        match paths {
+            Paths::Absolute => abs_path.to_string(),
+            Paths::Relative => Parser::relative_path(abs_path, root),
+            Paths::RelativeWithinRoot => match Path::new(abs_path).starts_with(root) {
+                true => Parser::relative_path(abs_path, root),
+                false => abs_path.to_string(),
+            },
+        }
+    }
+
+    pub(crate) fn relative_path(src_path: &str, base_path: &str) -> String{
         let abs_src = Parser::norm_path(src_path);
         let abs_base = Parser::norm_path(base_path);
 
@@ -345,7 +666,7 @@ impl Parser{
 }
 
 impl Iterator for Parser{
-    type Item = String;
+    type Item = Vec<CompileCommand>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // println!("parseable: {}", self.parserable());
@@ -363,6 +684,7 @@ impl Iterator for Parser{
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::Value;
 
     #[test]
     fn test_parser_command() {
@@ -399,11 +721,180 @@ mod tests {
         ];
 
         for (i, (input, expected)) in test_cases.iter().enumerate() {
-            let result = parser.parser_command(input);
+            let result = parser.parser_command(input).map(|items| {
+                let items: Vec<Value> = items.iter().map(|item| item.to_json(false)).collect();
+                serde_json::to_string_pretty(&items).unwrap()
+            });
             assert_eq!(result, Some(expected.to_string()), "Test case {} failed", i);
         }
     }
 
+    #[test]
+    fn test_parse_wrapper() {
+        let reader = crate::reader::MockReader(); 
+
+        let mut parser: Parser = Parser::new(
+            Box::new(reader), 
+            Some(String::from("/coder/build")),
+            false
+        );
+
+        let items = parser.parser_command("ccache arm-none-eabi-gcc -mcpu=cortex-m4 -c main.c\n").unwrap();
+        assert_eq!(items[0].arguments, ["arm-none-eabi-gcc", "-mcpu=cortex-m4", "-c"]);
+
+        parser.set_config(Config {
+            compilers: vec!["xcc".to_string()],
+            source_extensions: vec![".xc".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(parser.parser_command("gcc -c main.c\n"), None);
+        let items = parser.parser_command("/opt/bin/xcc -c main.xc\n").unwrap();
+        assert_eq!(items[0].file, "main.xc");
+    }
+
+    #[test]
+    fn test_parse_exec() {
+        let reader = crate::reader::MockReader(); 
+
+        let mut parser: Parser = Parser::new(
+            Box::new(reader), 
+            Some(String::from("/coder/build")),
+            false
+        );
+
+        let exec = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+        let items = parser.parse_exec("/coder/build/src", &exec(&["/usr/bin/gcc", "-DNAME=\"a b\"", "-c", "main.c"])).unwrap();
+        assert_eq!(items, vec![CompileCommand::new(
+            "/coder/build",
+            "src/main.c",
+            exec(&["/usr/bin/gcc", "-DNAME=\"a b\"", "-c"])
+        )]);
+
+        let logged = parser.parser_command("/usr/bin/gcc '-DNAME=\"a b\"' -c main.c\n").unwrap();
+        assert_eq!(logged, items);
+        assert_eq!(items[0].to_json(true)["command"], "/usr/bin/gcc '-DNAME=\"a b\"' -c");
+
+        assert_eq!(parser.parse_exec("/coder/build", &exec(&["make", "CC=gcc", "main.c"])), None);
+        assert_eq!(parser.parse_exec("/coder/build", &exec(&["clang", "-cc1", "main.c"])), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_system_includes_relative_compiler() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("ccjson_relative_cc");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("tools")).unwrap();
+        fs::create_dir_all(dir.join("build")).unwrap();
+        let cc = dir.join("tools/arm-gcc");
+        fs::write(&cc, "#!/bin/sh\necho '#include <...> search starts here:' >&2\necho ' /opt/arm/include' >&2\necho 'End of search list.' >&2\n").unwrap();
+        fs::set_permissions(&cc, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let root = dir.to_str().unwrap();
+        let mut parser: Parser = Parser::new(
+            Box::new(crate::reader::MockReader()),
+            Some(root.to_string()),
+            false
+        );
+        parser.set_config(Config { system_includes: Some(Mode::Isystem), ..Default::default() });
+        assert_eq!(parser.parse_log_line(&format!("make: Entering directory '{}/build'\n", root)), None);
+        let items = parser.parser_command("../tools/arm-gcc -c ../a.c\n").unwrap();
+        assert_eq!(items[0].arguments, ["../tools/arm-gcc", "-c", "-isystem", "/opt/arm/include"]);
+
+        parser.set_config(Config { system_includes: Some(Mode::QueryDriver), ..Default::default() });
+        parser.parser_command("../tools/arm-gcc -c ../a.c\n").unwrap();
+        assert_eq!(parser.query_drivers().iter().collect::<Vec<_>>(), [&format!("{}/tools/arm-*", root)]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_directory() {
+        let reader = crate::reader::MockReader(); 
+
+        let mut parser: Parser = Parser::new(
+            Box::new(reader), 
+            Some(String::from("/coder/build")),
+            false
+        );
+
+        assert_eq!(parser.parse_log_line("make: Entering directory '/coder/build'\n"), None);
+        assert_eq!(parser.parse_log_line("make[1]: Entering directory `/coder/build/src'\n"), None);
+        assert_eq!(parser.directory, "/coder/build/src");
+        assert_eq!(parser.parse_log_line("make[2]: Entering directory '/coder/build/src/lib'\n"), None);
+        assert_eq!(parser.directory, "/coder/build/src/lib");
+        assert_eq!(parser.parse_log_line("make[2]: Leaving directory '/coder/build/src/lib'\n"), None);
+        assert_eq!(parser.directory, "/coder/build/src");
+        assert_eq!(parser.parse_log_line("+ cd ../test\n"), None);
+        assert_eq!(parser.directory, "/coder/build/test");
+        assert_eq!(parser.parse_log_line("make[1]: Leaving directory '/coder/build/src'\n"), None);
+        assert_eq!(parser.directory, "/coder/build");
+    }
+
+    #[test]
+    fn test_parse_dialects() {
+        let reader = crate::reader::MockReader(); 
+
+        let mut parser: Parser = Parser::new(
+            Box::new(reader), 
+            Some(String::from("/coder")),
+            false
+        );
+        parser.add_dialect(dialect::by_name("cmake").unwrap());
+        parser.add_dialect(dialect::by_name("ninja").unwrap());
+        assert_eq!(parser.dialects(), ["ninja", "cmake", "make"]);
+
+        assert_eq!(parser.parse_log_line("ninja: Entering directory `build'\n"), None);
+        assert_eq!(parser.directory, "/coder/build");
+        let items = parser.parse_log_line("[12/340] /usr/bin/cc -Iinclude -c ../src/foo.c -o src/foo.o\n").unwrap();
+        assert_eq!(items, vec![CompileCommand::new(
+            "/coder",
+            "src/foo.c",
+            ["/usr/bin/cc", "-Ibuild/include", "-c", "-o", "build/src/foo.o"].iter().map(|s| s.to_string()).collect()
+        )]);
+
+        assert_eq!(parser.parse_log_line("[ 42%] Building C object src/CMakeFiles/foo.dir/foo.c.o\n"), None);
+        let items = parser.parse_log_line("cd /coder/build/src && /usr/bin/cc -DFOO -I/coder/include -o CMakeFiles/foo.dir/foo.c.o -c /coder/src/foo.c\n").unwrap();
+        assert_eq!(items, vec![CompileCommand::new(
+            "/coder",
+            "src/foo.c",
+            ["/usr/bin/cc", "-DFOO", "-Iinclude", "-o", "build/src/CMakeFiles/foo.dir/foo.c.o", "-c"].iter().map(|s| s.to_string()).collect()
+        )]);
+        assert_eq!(parser.directory, "/coder/build");
+    }
+
+    #[test]
+    fn test_parse_libtool() {
+        let reader = crate::reader::MockReader(); 
+
+        let mut parser: Parser = Parser::new(
+            Box::new(reader), 
+            Some(String::from("/coder/build")),
+            false
+        );
+
+        let items = parser.parser_command("/bin/bash ../libtool  --tag=CC   --mode=compile gcc -DHAVE_CONFIG_H -I. -c -o foo.lo foo.c\n").unwrap();
+        assert_eq!(items, vec![CompileCommand::new(
+            "/coder/build",
+            "foo.c",
+            ["gcc", "-DHAVE_CONFIG_H", "-I.", "-c", "-o", "foo.lo"].iter().map(|s| s.to_string()).collect()
+        )]);
+
+        assert_eq!(parser.parser_command("libtool: compile:  gcc -DHAVE_CONFIG_H -I. -c foo.c  -fPIC -DPIC -o .libs/foo.o\n"), None);
+        assert_eq!(parser.parser_command("libtool: compile:  gcc -DHAVE_CONFIG_H -I. -c foo.c -o foo.o >/dev/null 2>&1\n"), None);
+
+        let items = parser.parser_command("libtool: compile:  gcc -I. -c bar.c -o bar.o >/dev/null 2>&1\n").unwrap();
+        assert_eq!(items[0].arguments, ["gcc", "-I.", "-c", "-o", "bar.o"]);
+
+        // 同一源文件以不同参数编进两个库
+        let items = parser.parser_command("libtool: compile:  gcc -DLIBA -I. -c util.c  -fPIC -DPIC -o .libs/liba_la-util.o\n").unwrap();
+        assert_eq!(items[0].arguments, ["gcc", "-DLIBA", "-I.", "-c", "-fPIC", "-DPIC", "-o", ".libs/liba_la-util.o"]);
+        assert_eq!(parser.parser_command("libtool: compile:  gcc -DLIBA -I. -c util.c -o liba_la-util.o >/dev/null 2>&1\n"), None);
+        let items = parser.parser_command("libtool: compile:  gcc -DLIBB -I. -c util.c  -fPIC -DPIC -o .libs/libb_la-util.o\n").unwrap();
+        assert_eq!(items[0].arguments, ["gcc", "-DLIBB", "-I.", "-c", "-fPIC", "-DPIC", "-o", ".libs/libb_la-util.o"]);
+        assert_eq!(parser.parser_command("libtool: compile:  gcc -DLIBB -I. -c util.c -o libb_la-util.o >/dev/null 2>&1\n"), None);
+    }
+
     #[test]
     fn test_norm_path(){
         let src = "..//./../a//b/c/";
@@ -443,6 +934,44 @@ mod tests {
         assert_eq!(abs_path, "/cc/tests/code/config.txt");
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_resolve_symlinks(){
+        let dir = std::env::temp_dir().join("ccjson_symlinks");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("real/src")).unwrap();
+        fs::create_dir_all(dir.join("include")).unwrap();
+        fs::write(dir.join("real/src/main.c"), "").unwrap();
+        std::os::unix::fs::symlink(dir.join("real/src"), dir.join("real/link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("proj")).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap().to_string_lossy().into_owned();
+
+        let proj = format!("{}/proj", dir);
+        let mut parser = Parser::new(Box::new(crate::reader::MockReader()), Some(proj.clone()), false);
+        assert_eq!(parser.absolute_path("link/../main.c"), format!("{}/proj/main.c", dir));
+
+        parser.set_config(Config { resolve_symlinks: true, ..Default::default() });
+        assert_eq!(parser.absolute_path("link/../src/main.c"), format!("{}/real/src/main.c", dir));
+        assert_eq!(parser.absolute_path("missing/../x.c"), format!("{}/real/x.c", dir));
+
+        // 保留用户看到的根目录
+        parser.pwd = Some(proj.clone());
+        parser.set_config(Config { resolve_symlinks: true, keep_root: true, ..Default::default() });
+        assert_eq!(parser.absolute_path("link/../src/main.c"), format!("{}/proj/src/main.c", dir));
+        assert_eq!(parser.build_dir, proj);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rebase(){
+        assert_eq!(Parser::rebase("/coder/build/src/a.c", "/coder/build", Paths::Relative), "src/a.c");
+        assert_eq!(Parser::rebase("/usr/include", "/coder/build", Paths::Relative), "../../usr/include");
+        assert_eq!(Parser::rebase("/coder/build/src/a.c", "/coder/build", Paths::Absolute), "/coder/build/src/a.c");
+        assert_eq!(Parser::rebase("/coder/build/src/a.c", "/coder/build", Paths::RelativeWithinRoot), "src/a.c");
+        assert_eq!(Parser::rebase("/usr/include", "/coder/build", Paths::RelativeWithinRoot), "/usr/include");
+        assert_eq!(Parser::rebase("/coder/build2/a.c", "/coder/build", Paths::RelativeWithinRoot), "/coder/build2/a.c");
+    }
+
     #[test]
     fn test_relative_path(){
         assert_eq!(Parser::relative_path("/", "/"), ".");
@@ -475,6 +1004,7 @@ mod tests {
     }
     
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_find_target() {
         assert_eq!(find_target!("hello", "world!"), false);
         assert_eq!(find_target!("hello world", "world"), true);
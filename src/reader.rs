@@ -1,8 +1,37 @@
-use std::{fs::File, io::{stdin, BufRead, BufReader, ErrorKind, Stdin}};
+use std::{fs::{self, File}, path::{Path, PathBuf}, io::{self, stdin, BufRead, BufReader, ErrorKind, Read, Stdin, Write}, process::{Child, Command, ExitStatus, Stdio}, sync::{mpsc::{self, Receiver, Sender}, Arc, Mutex}, thread, time::{Duration, Instant}};
+
+use regex::Regex;
+
+/// Input handed to the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    /// A line of build log.
+    Line(String),
+    /// A program execution captured from the build, with its exact argv.
+    Exec {
+        directory: String,
+        arguments: Vec<String>,
+    },
+}
 
 pub trait Reader {
     fn read_line(&mut self) -> Option<String>;
     fn readable(&self) -> bool;
+
+    fn read_record(&mut self) -> Option<Record> {
+        self.read_line().map(Record::Line)
+    }
+
+    /// Exit code of the process producing the input, if there is one.
+    fn exit_code(&self) -> Option<i32> {
+        None
+    }
+
+    /// Whether the input is already shown on the terminal, as the output of
+    /// a build command is.
+    fn echoes(&self) -> bool {
+        false
+    }
 }
 
 pub struct StdinReader { 
@@ -15,6 +44,43 @@ pub struct FileReader {
     eof: bool,
 }
 
+/// Reads a log file which is still being written, like `tail -F`: waits for
+/// appended data, reopens the file when it is rotated or truncated, and stops
+/// once a line matches `until` or nothing is appended for `timeout`.
+pub struct FollowReader {
+    filename: String,
+    reader: Option<BufReader<File>>,
+    position: u64,
+    partial: Vec<u8>,
+    timeout: Duration,
+    until: Option<Regex>,
+    eof: bool,
+}
+
+/// How the followed file changed under the reader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// Replaced by a new file, or opened for the first time.
+    Rotated,
+    Truncated,
+}
+
+/// Scans a Kbuild output tree for `.*.o.cmd` files and reads the `cmd_` or
+/// `savedcmd_` line recorded in each of them.
+pub struct KbuildReader {
+    files: std::vec::IntoIter<PathBuf>,
+    eof: bool,
+}
+
+/// Runs a build command and reads its stdout and stderr line by line, while
+/// teeing both to the terminal and optionally to a log file.
+pub struct CommandReader {
+    receiver: Receiver<String>,
+    child: Child,
+    status: Option<ExitStatus>,
+    eof: bool,
+}
+
 impl FileReader {  
     pub fn new(filename: &String) -> FileReader{
         FileReader {
@@ -54,6 +120,181 @@ impl Reader for FileReader{
     }        
 }
 
+impl FollowReader {
+    const INTERVAL: Duration = Duration::from_millis(200);
+
+    pub fn new(filename: &str, timeout: Duration, until: Option<Regex>) -> FollowReader {
+        FollowReader {
+            filename: filename.to_string(),
+            reader: None,
+            position: 0,
+            partial: Vec::new(),
+            timeout,
+            until,
+            eof: false,
+        }
+    }
+
+    /// 文件被替换(inode 改变)或被截断时需要重新打开
+    fn changed(&self) -> Option<Change> {
+        let reader = match &self.reader {
+            Some(reader) => reader,
+            None => return Some(Change::Rotated),
+        };
+        let (current, latest) = match (reader.get_ref().metadata(), fs::metadata(&self.filename)) {
+            (Ok(current), Ok(latest)) => (current, latest),
+            // 文件暂时不存在, 继续读取已打开的文件
+            _ => return None,
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if current.ino() != latest.ino() || current.dev() != latest.dev() {
+                return Some(Change::Rotated);
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = current;
+
+        (latest.len() < self.position).then_some(Change::Truncated)
+    }
+
+    fn reopen(&mut self) -> bool {
+        match File::open(&self.filename) {
+            Ok(file) => {
+                self.reader = Some(BufReader::new(file));
+                self.position = 0;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl Reader for FollowReader {
+    fn read_line(&mut self) -> Option<String> {
+        let mut idle = Instant::now();
+        loop {
+            if self.eof {
+                return None;
+            }
+
+            if let Some(reader) = self.reader.as_mut() {
+                match reader.read_until(b'\n', &mut self.partial) {
+                    Ok(0) => (),
+                    Ok(size) => {
+                        self.position += size as u64;
+                        idle = Instant::now();
+                        // 写入方可能只写了半行, 等待换行符
+                        if self.partial.ends_with(b"\n") {
+                            let line = String::from_utf8_lossy(&self.partial).into_owned();
+                            self.partial.clear();
+                            if self.until.as_ref().is_some_and(|r| r.is_match(&line)) {
+                                self.eof = true;
+                            }
+                            return Some(line);
+                        }
+                        continue;
+                    }
+                    Err(e) => panic!("read line error: {:?}", e),
+                }
+            }
+
+            if let Some(change) = self.changed() {
+                let partial = std::mem::take(&mut self.partial);
+                if self.reopen() {
+                    idle = Instant::now();
+                    // 轮转前写入的半行属于旧文件, 作为它的最后一行; 截断时丢弃
+                    if change == Change::Rotated && !partial.is_empty() {
+                        let line = String::from_utf8_lossy(&partial).into_owned();
+                        if self.until.as_ref().is_some_and(|r| r.is_match(&line)) {
+                            self.eof = true;
+                        }
+                        return Some(line);
+                    }
+                    continue;
+                }
+                self.partial = partial;
+            }
+
+            if idle.elapsed() >= self.timeout {
+                self.eof = true;
+                if self.partial.is_empty() {
+                    return None;
+                }
+                let line = String::from_utf8_lossy(&self.partial).into_owned();
+                self.partial.clear();
+                return Some(line);
+            }
+            thread::sleep(FollowReader::INTERVAL.min(self.timeout));
+        }
+    }
+
+    fn readable(&self) -> bool {
+        !self.eof
+    }
+}
+
+impl KbuildReader {
+    pub fn new(tree: &str) -> KbuildReader {
+        let mut files = Vec::new();
+        KbuildReader::scan(Path::new(tree), &mut files);
+        if files.is_empty() {
+            eprintln!("Warning: no .cmd files found in {}", tree);
+        }
+        files.sort();
+
+        KbuildReader {
+            files: files.into_iter(),
+            eof: false,
+        }
+    }
+
+    fn scan(dir: &Path, files: &mut Vec<PathBuf>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            // 不跟随符号链接, 避免 source 链接造成的重复和循环
+            match entry.file_type() {
+                Ok(t) if t.is_dir() && name != ".git" => KbuildReader::scan(&path, files),
+                Ok(t) if t.is_file() && name.starts_with('.') && name.ends_with(".o.cmd") => files.push(path),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Reader for KbuildReader {
+    fn read_line(&mut self) -> Option<String> {
+        for file in self.files.by_ref() {
+            let content = match fs::read(&file) {
+                Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+                Err(_) => continue,
+            };
+            let line = content.lines()
+                .find(|l| l.starts_with("cmd_") || l.starts_with("savedcmd_"));
+            if let Some(line) = line {
+                // .cmd 文件中的 '#' 被转义为 "\#"
+                return Some(line.replace("\\#", "#") + "\n");
+            }
+        }
+
+        self.eof = true;
+        None
+    }
+
+    fn readable(&self) -> bool {
+        !self.eof
+    }
+}
+
 impl StdinReader{
     pub fn new() -> StdinReader{
         StdinReader{
@@ -99,6 +340,95 @@ impl Reader for StdinReader{
 }
 
 
+impl CommandReader {
+    pub fn new(command: &[String], log: Option<&String>) -> Result<CommandReader, String> {
+        // 先创建日志文件, 失败时不必再启动构建
+        let log = match log {
+            Some(l) => Some(Arc::new(Mutex::new(
+                File::create(l).map_err(|e| format!("can't create {}: {}", l, e))?
+            ))),
+            None => None,
+        };
+
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("can't run {}: {}", command.join(" "), e))?;
+
+        let (sender, receiver) = mpsc::channel();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        CommandReader::tee(stdout, io::stdout(), log.clone(), sender.clone());
+        CommandReader::tee(stderr, io::stderr(), log, sender);
+
+        Ok(CommandReader {
+            receiver,
+            child,
+            status: None,
+            eof: false,
+        })
+    }
+
+    /// 在后台线程中逐行转发子进程的输出，原样写回终端和日志文件，并交给解析器
+    fn tee<R, W>(source: R, mut terminal: W, log: Option<Arc<Mutex<File>>>, sender: Sender<String>)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        thread::spawn(move || {
+            let mut reader = BufReader::new(source);
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => (),
+                }
+
+                let _ = terminal.write_all(&buf);
+                let _ = terminal.flush();
+                if let Some(log) = &log {
+                    let _ = log.lock().unwrap().write_all(&buf);
+                }
+                // 非 utf-8 编码的字符以替换字符代替，不影响后续行的解析
+                if sender.send(String::from_utf8_lossy(&buf).into_owned()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl Reader for CommandReader {
+    fn read_line(&mut self) -> Option<String> {
+        match self.receiver.recv() {
+            Ok(line) => Some(line),
+            Err(_) => {
+                // stdout 和 stderr 都已关闭，等待构建进程退出
+                self.eof = true;
+                self.status = Some(self.child.wait().expect("Error: failed to wait for build command."));
+                None
+            }
+        }
+    }
+
+    fn readable(&self) -> bool {
+        !self.eof
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        // 被信号终止时没有退出码，按失败处理
+        self.status.map(|s| s.code().unwrap_or(1))
+    }
+
+    fn echoes(&self) -> bool {
+        true
+    }
+}
+
+
 pub struct MockReader();
 
 impl Reader for MockReader{
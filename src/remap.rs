@@ -0,0 +1,160 @@
+use std::fs;
+
+use serde_json::Value;
+
+use crate::command::{self, CompileCommand};
+
+/// Flags which take a path joined to them, as in `-I/usr/include`.
+const PATH_FLAGS: [&str; 11] = [
+    "-I", "-isystem", "-iquote", "-idirafter", "-include", "-imacros",
+    "--sysroot=", "-isysroot", "-o", "-MF", "-L",
+];
+
+/// Prefix maps for absolute paths, e.g. from where the sources live in a
+/// build container to where they are checked out on the host.
+#[derive(Debug, Clone, Default)]
+pub struct Remap {
+    /// Longest prefix first.
+    maps: Vec<(String, String)>,
+}
+
+impl Remap {
+    pub fn new() -> Remap {
+        Remap::default()
+    }
+
+    /// Adds a map written `FROM=TO`.
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        let (from, to) = spec.split_once('=')
+            .filter(|(from, _)| from.starts_with('/'))
+            .ok_or(format!("invalid remap {}, expected /from=/to", spec))?;
+        self.maps.push((from.trim_end_matches('/').to_string(), to.trim_end_matches('/').to_string()));
+        self.maps.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.maps.is_empty()
+    }
+
+    /// Maps an absolute path by its longest matching prefix, which must end
+    /// at a path component.
+    pub fn path(&self, path: &str) -> Option<String> {
+        self.maps.iter().find_map(|(from, to)| {
+            let rest = path.strip_prefix(from.as_str())?;
+            match rest.is_empty() || rest.starts_with('/') {
+                true if to.is_empty() && rest.is_empty() => Some("/".to_string()),
+                true => Some(format!("{}{}", to, rest)),
+                false => None,
+            }
+        })
+    }
+
+    /// Maps the path an argument holds, alone or joined to a flag.
+    pub fn arg(&self, arg: &str) -> String {
+        if let Some(p) = self.path(arg) {
+            return p;
+        }
+        PATH_FLAGS.iter()
+            .filter_map(|flag| Some((flag, arg.strip_prefix(flag)?)))
+            .find_map(|(flag, p)| self.path(p).map(|p| format!("{}{}", flag, p)))
+            .unwrap_or_else(|| arg.to_string())
+    }
+
+    pub fn apply(&self, command: &mut CompileCommand) {
+        if self.is_empty() {
+            return;
+        }
+        command.directory = self.arg(&command.directory);
+        command.file = self.arg(&command.file);
+        for arg in command.arguments.iter_mut() {
+            *arg = self.arg(arg);
+        }
+    }
+
+    /// Applies the maps to an entry of an existing database.
+    pub fn apply_json(&self, entry: &mut Value) {
+        let Some(entry) = entry.as_object_mut() else { return };
+        for key in ["directory", "file", "output"] {
+            if let Some(Value::String(s)) = entry.get_mut(key) {
+                *s = self.arg(s);
+            }
+        }
+        if let Some(Value::Array(args)) = entry.get_mut("arguments") {
+            for arg in args.iter_mut() {
+                if let Value::String(s) = arg {
+                    *s = self.arg(s);
+                }
+            }
+        }
+        if let Some(Value::String(command)) = entry.get_mut("command") {
+            let args: Vec<String> = command::split_command(command).iter().map(|a| self.arg(a)).collect();
+            *command = command::join_command(&args);
+        }
+    }
+}
+
+/// Applies the maps to the database at `path`, writing the result to
+/// `output`. Returns the number of entries.
+pub fn remap_database(path: &str, output: &str, remap: &Remap) -> Result<usize, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let mut entries: Value = serde_json::from_str(&content).map_err(|e| format!("invalid database {}: {}", path, e))?;
+    let entries = entries.as_array_mut().ok_or(format!("invalid database {}: not an array", path))?;
+    for entry in entries.iter_mut() {
+        remap.apply_json(entry);
+    }
+    let content = serde_json::to_string_pretty(entries).unwrap();
+    fs::write(output, content).map_err(|e| format!("can't write {}: {}", output, e))?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path() {
+        let mut remap = Remap::new();
+        remap.add("/workspace=/home/alice/proj").unwrap();
+        remap.add("/workspace/third_party/=/opt/vendor/").unwrap();
+        assert_eq!(remap.path("/workspace"), Some("/home/alice/proj".to_string()));
+        assert_eq!(remap.path("/workspace/src/a.c"), Some("/home/alice/proj/src/a.c".to_string()));
+        assert_eq!(remap.path("/workspace/third_party/z.c"), Some("/opt/vendor/z.c".to_string()));
+        assert_eq!(remap.path("/workspace2/a.c"), None);
+        assert_eq!(remap.arg("-I/workspace/include"), "-I/home/alice/proj/include");
+        assert_eq!(remap.arg("--sysroot=/workspace/sysroot"), "--sysroot=/home/alice/proj/sysroot");
+        assert_eq!(remap.arg("-DPATH=/workspace"), "-DPATH=/workspace");
+
+        let mut root = Remap::new();
+        root.add("/=/sysroot").unwrap();
+        assert_eq!(root.path("/usr/include"), Some("/sysroot/usr/include".to_string()));
+
+        assert!(remap.add("workspace").is_err());
+        assert!(remap.add("relative=/x").is_err());
+    }
+
+    #[test]
+    fn test_apply_json() {
+        let mut remap = Remap::new();
+        remap.add("/workspace=/src").unwrap();
+        let mut entry: Value = serde_json::from_str(r#"{
+            "directory": "/workspace/build",
+            "command": "gcc -I/workspace/include -c /workspace/a.c",
+            "file": "/workspace/a.c",
+            "output": "/workspace/build/a.o"
+        }"#).unwrap();
+        remap.apply_json(&mut entry);
+        assert_eq!(entry["directory"], "/src/build");
+        assert_eq!(entry["command"], "gcc -I/src/include -c /src/a.c");
+        assert_eq!(entry["file"], "/src/a.c");
+        assert_eq!(entry["output"], "/src/build/a.o");
+
+        let mut entry = serde_json::json!({
+            "directory": "/workspace",
+            "command": "gcc -DMSG=\"a b\" -I\"/workspace/my dir\" -c a.c",
+            "file": "a.c",
+        });
+        remap.apply_json(&mut entry);
+        assert_eq!(entry["command"], "gcc '-DMSG=a b' '-I/src/my dir' -c a.c");
+    }
+}
@@ -0,0 +1,220 @@
+use regex::Regex;
+
+use crate::config::Rewrite;
+
+/// Names of the built-in profiles.
+pub const PROFILES: [&str; 1] = ["gcc-to-clang"];
+
+/// GCC flags clang doesn't know, mostly met with embedded and kernel
+/// toolchains.
+const GCC_TO_CLANG: &[&str] = &[
+    "-mno-sdata",
+    "-mlongcalls",
+    "-mtext-section-literals",
+    "-mfix-esp32-psram-cache-issue",
+    "-mrecord-mcount",
+    "-mindirect-branch-register",
+    "-mno-fdpic",
+    "-fstack-usage",
+    "-fconserve-stack",
+    "-fstrict-volatile-bitfields",
+    "-fno-shrink-wrap",
+    "-fno-var-tracking-assignments",
+    "-fno-allow-store-data-races",
+    "re:^-fno-tree-.*$",
+    "re:^-fno-ipa-.*$",
+    "re:^-fsched-pressure$",
+    "re:^-fdump-.*$",
+    "re:^-mindirect-branch=.*$",
+    "re:^-mpreferred-stack-boundary=.*$",
+    "re:^-Wno-(packed-not-aligned|format-truncation|stringop-.*|restrict|maybe-uninitialized)$",
+];
+
+/// Flag matched exactly, or by a regex when written `re:<regex>`.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn new(spec: &str) -> Result<Pattern, String> {
+        match spec.strip_prefix("re:") {
+            Some(re) => Regex::new(re)
+                .map(Pattern::Regex)
+                .map_err(|e| format!("invalid rewrite rule {}: {}", spec, e)),
+            None => Ok(Pattern::Exact(spec.to_string())),
+        }
+    }
+
+    pub fn is_match(&self, arg: &str) -> bool {
+        match self {
+            Pattern::Exact(s) => s == arg,
+            Pattern::Regex(re) => re.is_match(arg),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Rule {
+    Remove(Pattern),
+    /// Regex patterns may refer to their groups in the replacement (`$1`).
+    Replace(Pattern, String),
+    /// Inserted right after the compiler.
+    Prepend(String),
+    Append(String),
+}
+
+/// Rewrites the arguments of every entry, e.g. to drop GCC flags which make
+/// clangd complain.
+#[derive(Debug, Clone, Default)]
+pub struct Rewriter {
+    rules: Vec<Rule>,
+}
+
+impl Rewriter {
+    pub fn new() -> Rewriter {
+        Rewriter::default()
+    }
+
+    /// Builds the rules of the `[rewrite]` table, profiles first.
+    pub fn from_config(rewrite: &Rewrite) -> Result<Rewriter, String> {
+        let mut rewriter = Rewriter::new();
+        for name in &rewrite.profiles {
+            rewriter.add_profile(name)?;
+        }
+        for spec in &rewrite.remove {
+            rewriter.add(Rule::Remove(Pattern::new(spec)?));
+        }
+        for (from, to) in &rewrite.replace {
+            rewriter.add(Rule::Replace(Pattern::new(from)?, to.clone()));
+        }
+        for arg in &rewrite.prepend {
+            rewriter.add(Rule::Prepend(arg.clone()));
+        }
+        for arg in &rewrite.append {
+            rewriter.add(Rule::Append(arg.clone()));
+        }
+        Ok(rewriter)
+    }
+
+    pub fn add(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    pub fn add_profile(&mut self, name: &str) -> Result<(), String> {
+        let flags = match name {
+            "gcc-to-clang" => GCC_TO_CLANG,
+            _ => return Err(format!("unknown rewrite profile {}, expected one of {}", name, PROFILES.join(", "))),
+        };
+        for flag in flags {
+            self.add(Rule::Remove(Pattern::new(flag)?));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Removed flags in the form of `.clangd` `Remove:`, where a trailing `*`
+    /// matches any suffix. Regexes which can't be written so are left out.
+    pub fn removed_flags(&self) -> Vec<String> {
+        let literal = |s: &str| s.chars().all(|c| c.is_ascii_alphanumeric() || "-_=+,:/".contains(c));
+        self.rules.iter().filter_map(|rule| match rule {
+            Rule::Remove(Pattern::Exact(s)) => Some(s.clone()),
+            Rule::Remove(Pattern::Regex(re)) => {
+                let body = re.as_str().strip_prefix('^')?;
+                match body.strip_suffix(".*$").or_else(|| body.strip_suffix(".*")) {
+                    Some(prefix) if literal(prefix) => Some(format!("{}*", prefix)),
+                    Some(_) => None,
+                    None => body.strip_suffix('$').filter(|b| literal(b)).map(|b| b.to_string()),
+                }
+            }
+            _ => None,
+        }).collect()
+    }
+
+    /// Applies the rules in order to `args`, whose first element is the
+    /// compiler and is left alone.
+    pub fn apply(&self, args: &mut Vec<String>) {
+        if args.is_empty() {
+            return;
+        }
+        let mut flags = args.split_off(1);
+        let mut prepended = 0;
+        for rule in &self.rules {
+            match rule {
+                Rule::Remove(p) => flags.retain(|a| !p.is_match(a)),
+                Rule::Replace(Pattern::Exact(from), to) => {
+                    flags.iter_mut().filter(|a| *a == from).for_each(|a| a.clone_from(to));
+                }
+                Rule::Replace(Pattern::Regex(re), to) => {
+                    for a in flags.iter_mut() {
+                        if re.is_match(a) {
+                            *a = re.replace(a, to.as_str()).into_owned();
+                        }
+                    }
+                }
+                Rule::Prepend(arg) => {
+                    flags.insert(prepended, arg.clone());
+                    prepended += 1;
+                }
+                Rule::Append(arg) => flags.push(arg.clone()),
+            }
+        }
+        args.extend(flags);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut rewriter = Rewriter::new();
+        rewriter.add(Rule::Remove(Pattern::new("-fstack-usage").unwrap()));
+        rewriter.add(Rule::Remove(Pattern::new("re:^-W").unwrap()));
+        rewriter.add(Rule::Replace(Pattern::new("re:^-mcpu=(.*)$").unwrap(), "-mcpu=$1+nofp".to_string()));
+        rewriter.add(Rule::Replace(Pattern::new("-O3").unwrap(), "-O2".to_string()));
+        rewriter.add(Rule::Prepend("-xc".to_string()));
+        rewriter.add(Rule::Prepend("-std=c11".to_string()));
+        rewriter.add(Rule::Append("-Wno-unknown-attributes".to_string()));
+
+        let mut args = strings(&["gcc", "-O3", "-Wall", "-fstack-usage", "-mcpu=cortex-m4", "-c"]);
+        rewriter.apply(&mut args);
+        assert_eq!(args, ["gcc", "-xc", "-std=c11", "-O2", "-mcpu=cortex-m4+nofp", "-c", "-Wno-unknown-attributes"]);
+
+        assert!(Pattern::new("re:(").is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut rewriter = Rewriter::new();
+        rewriter.add_profile("gcc-to-clang").unwrap();
+        let mut args = strings(&[
+            "xtensa-esp32-elf-gcc", "-mlongcalls", "-mno-sdata", "-fno-tree-loop-distribute-patterns",
+            "-fstack-usage", "-O2", "-DNDEBUG",
+        ]);
+        rewriter.apply(&mut args);
+        assert_eq!(args, ["xtensa-esp32-elf-gcc", "-O2", "-DNDEBUG"]);
+
+        // clang 也支持的参数要保留, 去掉 -mabi 会改变浮点 ABI
+        let mut args = strings(&["riscv64-unknown-elf-gcc", "-mabi=lp64d", "-Wno-unused-but-set-variable", "-fmerge-constants"]);
+        rewriter.apply(&mut args);
+        assert_eq!(args, ["riscv64-unknown-elf-gcc", "-mabi=lp64d", "-Wno-unused-but-set-variable", "-fmerge-constants"]);
+
+        let removed = rewriter.removed_flags();
+        assert!(removed.contains(&"-mlongcalls".to_string()));
+        assert!(removed.contains(&"-fno-tree-*".to_string()));
+        assert!(removed.contains(&"-fsched-pressure".to_string()));
+        assert!(!removed.iter().any(|f| f.contains('(')));
+
+        assert!(rewriter.add_profile("clang-to-gcc").is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::{collections::{BTreeMap, BTreeSet}, fmt};
+
+use serde::Serialize;
+
+use crate::{check::Report, command::CompileCommand};
+
+/// What a run read, found and wrote.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct RunStats {
+    /// Lines of the build log, or traced executions.
+    pub lines: usize,
+    /// Compiler invocations with source files.
+    pub commands: usize,
+    /// Entries written, header entries included.
+    pub entries: usize,
+    /// Entries dropped by the filters or as libtool duplicates.
+    pub dropped: usize,
+    /// Lines which gave no entry, by reason: `dialect` (progress and other
+    /// lines the dialect ignores), `directory` (directory changes),
+    /// `not-command`, `no-source` (compiler without source, e.g. linking)
+    /// and `not-compiler` (traced executions of other programs).
+    pub skipped: BTreeMap<String, usize>,
+    pub compilers: BTreeSet<String>,
+    /// Directories the compile commands ran in.
+    pub directories: BTreeSet<String>,
+    /// Exit code of the build command, if one was run.
+    pub exit_code: Option<i32>,
+    /// Check of the entries before they are remapped, when verifying.
+    pub check: Option<Report>,
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f, "ccjson: {} lines read, {} compile commands found, {} entries written",
+            self.lines, self.commands, self.entries
+        )?;
+        if self.dropped > 0 {
+            writeln!(f, "ccjson: {} entries dropped by filters or as duplicates", self.dropped)?;
+        }
+        if !self.skipped.is_empty() {
+            let skipped: Vec<String> = self.skipped.iter().map(|(r, n)| format!("{} {}", n, r)).collect();
+            writeln!(f, "ccjson: skipped lines: {}", skipped.join(", "))?;
+        }
+        let compilers: Vec<&str> = self.compilers.iter().map(|s| s.as_str()).collect();
+        writeln!(
+            f, "ccjson: {} compilers ({}), {} directories",
+            self.compilers.len(), compilers.join(", "), self.directories.len()
+        )
+    }
+}
+
+/// Summary of a compilation database.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DatabaseStats {
+    pub entries: usize,
+    pub files: usize,
+    pub directories: usize,
+    /// Number of entries by compiler.
+    pub compilers: BTreeMap<String, usize>,
+}
+
+impl DatabaseStats {
+    pub fn new(entries: &[CompileCommand]) -> DatabaseStats {
+        let mut compilers = BTreeMap::new();
+        for entry in entries {
+            if let Some(cc) = entry.arguments.first() {
+                *compilers.entry(cc.clone()).or_insert(0) += 1;
+            }
+        }
+        DatabaseStats {
+            entries: entries.len(),
+            files: entries.iter().map(|e| (&e.directory, &e.file)).collect::<BTreeSet<_>>().len(),
+            directories: entries.iter().map(|e| &e.directory).collect::<BTreeSet<_>>().len(),
+            compilers,
+        }
+    }
+}
+
+impl fmt::Display for DatabaseStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "entries:     {}", self.entries)?;
+        writeln!(f, "files:       {}", self.files)?;
+        writeln!(f, "directories: {}", self.directories)?;
+        writeln!(f, "compilers:")?;
+        for (cc, n) in &self.compilers {
+            writeln!(f, "  {:>6}  {}", n, cc)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_stats() {
+        let mut stats = RunStats { lines: 10, commands: 2, entries: 3, ..Default::default() };
+        stats.skipped.insert("dialect".to_string(), 5);
+        stats.skipped.insert("no-source".to_string(), 1);
+        stats.compilers.insert("gcc".to_string());
+        stats.directories.insert("/coder".to_string());
+        assert_eq!(stats.to_string(), "\
+ccjson: 10 lines read, 2 compile commands found, 3 entries written
+ccjson: skipped lines: 5 dialect, 1 no-source
+ccjson: 1 compilers (gcc), 1 directories
+");
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["skipped"]["dialect"], 5);
+        assert_eq!(json["exit_code"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_database_stats() {
+        let entry = |dir: &str, file: &str, cc: &str| CompileCommand::new(dir, file, vec![cc.to_string()]);
+        let stats = DatabaseStats::new(&[
+            entry("/a", "x.c", "gcc"),
+            entry("/a", "x.c", "gcc"),
+            entry("/b", "y.cpp", "g++"),
+        ]);
+        assert_eq!((stats.entries, stats.files, stats.directories), (3, 2, 2));
+        assert_eq!(stats.compilers["gcc"], 2);
+        assert!(stats.to_string().contains("       2  gcc\n"));
+    }
+}
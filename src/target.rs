@@ -0,0 +1,85 @@
+use crate::config::Config;
+
+/// Triple clang should use for `compiler`, from `[target] triples` or its
+/// cross prefix.
+pub fn triple(compiler: &str, config: &Config) -> Option<String> {
+    let name = compiler.rsplit('/').next().unwrap_or(compiler);
+    if config.target.skip.iter().any(|s| s == name) {
+        return None;
+    }
+    if let Some(t) = config.target.triples.get(name) {
+        return Some(t.clone());
+    }
+    config.cross_prefix(compiler).map(|p| p.to_string())
+}
+
+/// Adds `--target=<triple>` after the compiler unless the arguments already
+/// have one. Cpu names clang takes as `-mcpu` rather than in the triple are
+/// moved there, as for `xtensa-esp32-elf-gcc`.
+pub fn inject(args: &mut Vec<String>, config: &Config) {
+    let triple = match args.first().and_then(|cc| triple(cc, config)) {
+        Some(t) => t,
+        None => return,
+    };
+    if args.iter().any(|a| a.starts_with("--target=") || a == "-target") {
+        return;
+    }
+
+    let mut flags = vec![];
+    match triple.split('-').collect::<Vec<&str>>()[..] {
+        // ESP-IDF 的 clang 只认 xtensa-esp-elf, 芯片由 -mcpu 指定
+        ["xtensa", chip, "elf"] if chip.starts_with("esp") => {
+            flags.push("--target=xtensa-esp-elf".to_string());
+            if !args.iter().any(|a| a.starts_with("-mcpu=")) {
+                flags.push(format!("-mcpu={}", chip));
+            }
+        }
+        _ => flags.push(format!("--target={}", triple)),
+    }
+
+    // 交叉编译时 native 指的是编译机而非目标机
+    args.retain(|a| !matches!(a.as_str(), "-march=native" | "-mcpu=native" | "-mtune=native"));
+    args.splice(1..1, flags);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inject_args(args: &[&str], config: &Config) -> Vec<String> {
+        let mut args = args.iter().map(|s| s.to_string()).collect();
+        inject(&mut args, config);
+        args
+    }
+
+    #[test]
+    fn test_triple() {
+        let mut config = Config::default();
+        assert_eq!(triple("/opt/gcc/bin/arm-none-eabi-gcc", &config), Some("arm-none-eabi".to_string()));
+        assert_eq!(triple("gcc", &config), None);
+
+        config.target.skip.push("arm-none-eabi-gcc".to_string());
+        config.target.triples.insert("xcc".to_string(), "riscv32-unknown-elf".to_string());
+        config.compilers.push("xcc".to_string());
+        assert_eq!(triple("arm-none-eabi-gcc", &config), None);
+        assert_eq!(triple("/usr/bin/xcc", &config), Some("riscv32-unknown-elf".to_string()));
+    }
+
+    #[test]
+    fn test_inject() {
+        let config = Config::default();
+        assert_eq!(
+            inject_args(&["aarch64-linux-gnu-gcc", "-march=native", "-c"], &config),
+            ["aarch64-linux-gnu-gcc", "--target=aarch64-linux-gnu", "-c"]
+        );
+        assert_eq!(
+            inject_args(&["xtensa-esp32s3-elf-gcc", "-c"], &config),
+            ["xtensa-esp32s3-elf-gcc", "--target=xtensa-esp-elf", "-mcpu=esp32s3", "-c"]
+        );
+        assert_eq!(
+            inject_args(&["arm-none-eabi-gcc", "--target=thumbv7em-none-eabi", "-c"], &config),
+            ["arm-none-eabi-gcc", "--target=thumbv7em-none-eabi", "-c"]
+        );
+        assert_eq!(inject_args(&["gcc", "-march=native"], &config), ["gcc", "-march=native"]);
+    }
+}
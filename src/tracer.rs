@@ -0,0 +1,182 @@
+use std::{collections::HashSet, fs, io, os::unix::process::CommandExt, process::Command, ptr, sync::mpsc::{self, Receiver, Sender}, thread};
+
+use crate::reader::{Reader, Record};
+
+/// Runs a build command under `ptrace` and captures every program it executes,
+/// so compilers invoked silently (`@$(CC)`, `make -s`, ...) are still seen.
+pub struct TraceReader {
+    receiver: Receiver<Message>,
+    status: Option<i32>,
+    eof: bool,
+}
+
+enum Message {
+    Exec(Record),
+    Exit(i32),
+}
+
+impl TraceReader {
+    /// Starts the build command under `ptrace`, failing when it can't be run
+    /// or traced.
+    pub fn new(command: &[String]) -> Result<TraceReader, String> {
+        let build = command.to_vec();
+        let (sender, receiver) = mpsc::channel();
+
+        // ptrace 要求所有跟踪请求都来自同一个线程，因此启动和跟踪都在后台线程中完成
+        let (started, start) = mpsc::channel();
+        thread::spawn(move || {
+            let mut cmd = Command::new(&build[0]);
+            cmd.args(&build[1..]);
+            unsafe {
+                cmd.pre_exec(|| {
+                    // 不允许跟踪时(seccomp、ptrace_scope 或已被跟踪)启动失败, 而不是不跟踪地构建
+                    if libc::ptrace(libc::PTRACE_TRACEME, 0, ptr::null_mut::<libc::c_void>(), ptr::null_mut::<libc::c_void>()) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+
+            match cmd.spawn() {
+                Ok(child) => {
+                    started.send(Ok(())).unwrap();
+                    TraceReader::trace(child.id() as libc::pid_t, sender);
+                }
+                Err(e) => started.send(Err(e)).unwrap(),
+            }
+        });
+
+        start.recv().unwrap().map_err(|e| format!("can't run {}: {}", command.join(" "), e))?;
+        Ok(TraceReader {
+            receiver,
+            status: None,
+            eof: false,
+        })
+    }
+
+    fn trace(root: libc::pid_t, sender: Sender<Message>) {
+        let mut status: libc::c_int = 0;
+
+        // 子进程在 execve 之后停止，此时设置跟踪选项以继承到所有后代进程
+        unsafe {
+            libc::waitpid(root, &mut status, libc::__WALL);
+        }
+        if !libc::WIFSTOPPED(status) {
+            // 没有停止说明未被跟踪, 已经退出
+            let _ = sender.send(Message::Exit(TraceReader::exit_status(status)));
+            return;
+        }
+        unsafe {
+            let options = libc::PTRACE_O_TRACEEXEC
+                | libc::PTRACE_O_TRACEFORK
+                | libc::PTRACE_O_TRACEVFORK
+                | libc::PTRACE_O_TRACECLONE
+                | libc::PTRACE_O_EXITKILL;
+            libc::ptrace(libc::PTRACE_SETOPTIONS, root, ptr::null_mut::<libc::c_void>(), options as *mut libc::c_void);
+        }
+        if let Some(record) = TraceReader::exec_record(root) {
+            let _ = sender.send(Message::Exec(record));
+        }
+        TraceReader::resume(root, 0);
+
+        let mut traced: HashSet<libc::pid_t> = HashSet::from([root]);
+        loop {
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::__WALL) };
+            if pid < 0 {
+                // ECHILD: 所有进程都已退出
+                break;
+            }
+
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                traced.remove(&pid);
+                if pid == root {
+                    let _ = sender.send(Message::Exit(TraceReader::exit_status(status)));
+                }
+                continue;
+            }
+
+            if !libc::WIFSTOPPED(status) {
+                continue;
+            }
+
+            let signal = libc::WSTOPSIG(status);
+            let event = status >> 16;
+            let forward = if signal == libc::SIGTRAP && event == libc::PTRACE_EVENT_EXEC {
+                if let Some(record) = TraceReader::exec_record(pid) {
+                    let _ = sender.send(Message::Exec(record));
+                }
+                0
+            }else if signal == libc::SIGTRAP && event != 0 {
+                // fork/vfork/clone 事件
+                0
+            }else if signal == libc::SIGSTOP && traced.insert(pid) {
+                // 新进程被自动跟踪时的初始 SIGSTOP
+                0
+            }else {
+                signal
+            };
+            TraceReader::resume(pid, forward);
+        }
+    }
+
+    /// Exit code of a process which exited or was killed by a signal.
+    fn exit_status(status: libc::c_int) -> i32 {
+        match libc::WIFEXITED(status) {
+            true => libc::WEXITSTATUS(status),
+            false => 128 + libc::WTERMSIG(status),
+        }
+    }
+
+    fn resume(pid: libc::pid_t, signal: libc::c_int) {
+        unsafe {
+            libc::ptrace(libc::PTRACE_CONT, pid, ptr::null_mut::<libc::c_void>(), signal as *mut libc::c_void);
+        }
+    }
+
+    /// Reads the argv and working directory of a stopped process from `/proc`.
+    fn exec_record(pid: libc::pid_t) -> Option<Record> {
+        let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+        let directory = fs::read_link(format!("/proc/{}/cwd", pid)).ok()?;
+
+        // 每个参数都以 NUL 结尾, 只去掉最后一个, 保留空参数
+        let cmdline = cmdline.strip_suffix(&[0]).unwrap_or(&cmdline);
+        let arguments: Vec<String> = cmdline.split(|b| *b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect();
+
+        Some(Record::Exec {
+            directory: directory.to_str()?.to_string(),
+            arguments,
+        })
+    }
+}
+
+impl Reader for TraceReader {
+    fn read_line(&mut self) -> Option<String> {
+        match self.read_record()? {
+            Record::Exec { arguments, .. } => Some(arguments.join(" ")),
+            Record::Line(line) => Some(line),
+        }
+    }
+
+    fn readable(&self) -> bool {
+        !self.eof
+    }
+
+    fn read_record(&mut self) -> Option<Record> {
+        loop {
+            match self.receiver.recv() {
+                Ok(Message::Exec(record)) => return Some(record),
+                Ok(Message::Exit(code)) => self.status = Some(code),
+                Err(_) => {
+                    self.eof = true;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        self.status
+    }
+}
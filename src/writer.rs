@@ -1,15 +1,75 @@
-use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+use std::{fs::{self, File}, io::{BufWriter, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+
+const CLANGD_BEGIN: &str = "# >>> ccjson";
+const CLANGD_END: &str = "# <<< ccjson";
+
+/// Fragment of `.clangd` generated by ccjson, delimited by marker comments
+/// so that it can be replaced in place by the next run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Clangd {
+    /// Globs for `--query-driver`, which clangd only takes on its command line.
+    pub query_drivers: Vec<String>,
+    /// Flags for `CompileFlags: Remove:`.
+    pub remove: Vec<String>,
+    /// Diagnostics for `Diagnostics: Suppress:`.
+    pub suppress: Vec<String>,
+}
+
+impl Clangd {
+    pub fn to_yaml(&self) -> String {
+        let list = |items: &[String]| -> String {
+            let items: Vec<String> = items.iter().map(|s| serde_json::to_string(s).unwrap()).collect();
+            format!("[{}]", items.join(", "))
+        };
+
+        let mut yaml = format!("{}\n", CLANGD_BEGIN);
+        if !self.query_drivers.is_empty() {
+            yaml += &format!("# Start clangd with --query-driver={}\n", self.query_drivers.join(","));
+        }
+        yaml += "CompileFlags:\n  CompilationDatabase: .\n";
+        if !self.remove.is_empty() {
+            yaml += &format!("  Remove: {}\n", list(&self.remove));
+        }
+        if !self.suppress.is_empty() {
+            yaml += &format!("Diagnostics:\n  Suppress: {}\n", list(&self.suppress));
+        }
+        yaml += &format!("{}\n", CLANGD_END);
+        yaml
+    }
+
+    /// Replaces the fragment of an earlier run in `existing`, or appends it
+    /// as a new yaml document.
+    pub fn merge(&self, existing: &str) -> String {
+        let yaml = self.to_yaml();
+        if let Some(begin) = existing.find(CLANGD_BEGIN) {
+            if let Some(end) = existing[begin..].find(CLANGD_END) {
+                let end = begin + end + CLANGD_END.len();
+                let end = existing[end..].strip_prefix('\n').map_or(end, |_| end + 1);
+                return format!("{}{}{}", &existing[..begin], yaml, &existing[end..]);
+            }
+        }
+
+        match existing.trim().is_empty() {
+            true => yaml,
+            false if existing.ends_with('\n') => format!("{}---\n{}", existing, yaml),
+            false => format!("{}\n---\n{}", existing, yaml),
+        }
+    }
+}
 
 pub struct Writer{
     path: PathBuf,
     buffer: BufWriter<File>,
     items: u32,
     count: u32,
+    incremental: bool,
 }
 
 impl Writer{
-    pub fn new(target_dir: Option<&str>, items: u32) -> Writer{
-        let raw_path = match target_dir {
+    /// Directory the output files are written to, `./` when `target_dir`
+    /// is not a directory.
+    pub fn target_dir(target_dir: Option<&str>) -> &Path {
+        match target_dir {
             Some(t) => {
                 let res = Path::new(t);
                 if res.is_dir(){
@@ -19,17 +79,27 @@ impl Writer{
                 }
             },
             None => Path::new("./")
-        };
-        let path: PathBuf = raw_path.join("compile_commands.json");
+        }
+    }
+
+    pub fn new(target_dir: Option<&str>, items: u32) -> Writer{
+        let path: PathBuf = Writer::target_dir(target_dir).join("compile_commands.json");
         let buffer = BufWriter::new(File::create(&path).unwrap());
         Writer{
             path,
             buffer,
             items,
             count: 0,
+            incremental: false,
         }
     }   
 
+    /// Keeps the file a valid json array after every entry, so the database
+    /// can be used while it is still being generated.
+    pub fn set_incremental(&mut self, incremental: bool){
+        self.incremental = incremental;
+    }
+
     pub fn write(&mut self, str: &str){
         match str{
             "[\n" | "\n]" => { 
@@ -40,6 +110,12 @@ impl Writer{
                     self.buffer.write_all(b",\n").expect("Error: failed to write prefix."); 
                 }
                 self.buffer.write_all(str.as_bytes()).expect("Error: failed to write content."); 
+                if self.incremental {
+                    // 先写入结尾的 "\n]", 再回退到它之前, 下一次写入会将其覆盖
+                    self.buffer.write_all(b"\n]").expect("Error: failed to write suffix.");
+                    self.buffer.seek(SeekFrom::Current(-2)).expect("Error: failed to seek.");
+                    self.flush();
+                }
             }
         };
 
@@ -55,6 +131,22 @@ impl Writer{
         self.buffer.flush().unwrap();
     }
     
+    /// Writes the fragment into `.clangd` next to compile_commands.json,
+    /// keeping what the file already has besides an earlier fragment.
+    pub fn write_clangd(&self, clangd: &Clangd){
+        let path = self.path.with_file_name(".clangd");
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        fs::write(&path, clangd.merge(&existing)).expect("Error: failed to write .clangd.");
+    }
+
+    /// Writes compile_flags.txt, one flag per line.
+    pub fn write_compile_flags(target_dir: Option<&str>, flags: &[String]) -> PathBuf {
+        let path = Writer::target_dir(target_dir).join("compile_flags.txt");
+        let content: String = flags.iter().map(|f| format!("{}\n", f)).collect();
+        fs::write(&path, content).expect("Error: failed to write compile_flags.txt.");
+        path
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
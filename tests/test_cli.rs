@@ -0,0 +1,137 @@
+use std::{fs::{self, OpenOptions}, io::Write, process::{Command, Stdio}, thread, time::{Duration, Instant}};
+
+fn ccjson() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_ccjson"))
+}
+
+#[test]
+fn test_follow_writes_early(){
+    let dir = std::env::temp_dir().join("ccjson_cli_follow");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("build.log");
+    fs::write(&log, "gcc -c a.c\n").unwrap();
+
+    let root = dir.to_str().unwrap();
+    let mut child = ccjson()
+        .args(["-p", log.to_str().unwrap(), "-f", "--timeout", "10", "--until", "^DONE", "-d", root, "-o", root])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // 远少于 --sample-lines 行时就应写出条目, 而不是等到超时
+    let database = dir.join("compile_commands.json");
+    let start = Instant::now();
+    while !fs::read_to_string(&database).unwrap_or_default().contains("a.c") {
+        assert!(start.elapsed() < Duration::from_secs(5), "no entry written while following");
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    OpenOptions::new().append(true).open(&log).unwrap().write_all(b"DONE\n").unwrap();
+    assert!(child.wait().unwrap().success());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_follow_invalid_until(){
+    let output = ccjson()
+        .args(["-p", "build.log", "-f", "--until", "(DONE"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: invalid --until regex (DONE: "));
+}
+
+#[test]
+fn test_remap_after_verify(){
+    let dir = std::env::temp_dir().join("ccjson_cli_remap");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.c"), "int a;\n").unwrap();
+    let log = dir.join("build.log");
+    fs::write(&log, "gcc -c a.c\n").unwrap();
+
+    // 检查使用构建环境中的路径, 映射后的路径在本机并不存在
+    let root = dir.to_str().unwrap();
+    let output = ccjson()
+        .args(["-p", log.to_str().unwrap(), "-d", root, "-o", root, "--paths", "absolute"])
+        .args(["--remap", &format!("{}=/host/proj", root), "--verify"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let database = fs::read_to_string(dir.join("compile_commands.json")).unwrap();
+    assert!(database.contains("\"/host/proj/a.c\""));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_generate_flags_with_subcommand(){
+    let output = ccjson().args(["-p", "nonexist.log", "stats", "compile_commands.json"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = ccjson().args(["config", "show", "-x", "**/test/**"]).output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("**/test/**"));
+}
+
+#[test]
+fn test_stats(){
+    let dir = std::env::temp_dir().join("ccjson_cli_stats");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("build.log");
+    fs::write(&log, "make: Entering directory '/coder'\ngcc -c a.c\ngcc a.o -o a\n").unwrap();
+
+    let root = dir.to_str().unwrap();
+    let stats = dir.join("stats.json");
+    let output = ccjson()
+        .args(["-p", log.to_str().unwrap(), "-d", root, "-o", root, "--stats-json", stats.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("3 lines read, 1 compile commands found, 1 entries written"));
+    let stats: serde_json::Value = serde_json::from_str(&fs::read_to_string(&stats).unwrap()).unwrap();
+    assert_eq!(stats["skipped"]["no-source"], 1);
+
+    let output = ccjson().args(["-q", "-p", log.to_str().unwrap(), "-d", root, "-o", root]).output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_compile_flags_ignored_options(){
+    let dir = std::env::temp_dir().join("ccjson_cli_flags");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("build.log");
+    fs::write(&log, "gcc -DX -c a.c\n").unwrap();
+
+    let root = dir.to_str().unwrap();
+    let output = ccjson()
+        .args(["-p", log.to_str().unwrap(), "-d", root, "-o", root, "--compile-flags", "--headers", "--clangd"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: --headers, --clangd ignored when writing compile_flags.txt"));
+    assert_eq!(fs::read_to_string(dir.join("compile_flags.txt")).unwrap(), "-DX\n");
+    assert!(!dir.join(".clangd").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_trace_not_allowed(){
+    // 已被跟踪的进程不能再次 PTRACE_TRACEME, 内层的 ccjson 应当报错而不是不跟踪地构建
+    let dir = std::env::temp_dir().join("ccjson_cli_trace");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let root = dir.to_str().unwrap();
+    let inner = format!("{} -q -t -o {} -- sh -c 'exit 3' 2>{}/inner.err", env!("CARGO_BIN_EXE_ccjson"), root, root);
+    let output = ccjson()
+        .args(["-q", "-t", "-o", root, "--", "sh", "-c", &inner])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(fs::read_to_string(dir.join("inner.err")).unwrap().starts_with("Error: can't run sh -c exit 3: "));
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -1,6 +1,7 @@
-use ccjson::{parser, reader::FileReader};
+use ccjson::{command::CompileCommand, dialect::{Action, Dialect, State}, parser, reader::FileReader};
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_parseable(){
     let file = FileReader::new(&String::from("./tests/build.log"));
 
@@ -35,4 +36,70 @@ fn test_parse_line(){
     } 
 
     assert_eq!(times, 2);
-}
\ No newline at end of file
+}
+/// A dialect for a made-up build tool printing `[build] <dir> $ <command>`.
+struct Custom;
+
+impl Dialect for Custom {
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    fn parse(&mut self, line: &str, _state: &State) -> Option<Action> {
+        let (dir, command) = line.strip_prefix("[build] ")?.split_once(" $ ")?;
+        Some(Action::Command {
+            directory: Some(dir.to_string()),
+            command: command.to_string(),
+        })
+    }
+}
+
+#[test]
+fn test_custom_dialect(){
+    let dir = std::env::temp_dir().join("ccjson_dialect");
+    let _ = std::fs::create_dir_all(&dir);
+    let log = dir.join("build.log");
+    std::fs::write(&log, "[build] /coder/src $ gcc -DFOO \\\n  -c main.c\n").unwrap();
+
+    let file = FileReader::new(&log.to_str().unwrap().to_string());
+    let mut parser: parser::Parser = parser::Parser::new(
+        Box::new(file), 
+        Some(String::from("/coder")),
+        false,
+    );
+    parser.add_dialect(Box::new(Custom));
+    assert_eq!(parser.dialects(), ["custom", "make"]);
+
+    let items: Vec<CompileCommand> = parser.flatten().collect();
+    assert_eq!(items, vec![CompileCommand::new(
+        "/coder",
+        "src/main.c",
+        vec!["gcc".to_string(), "-DFOO".to_string(), "-c".to_string()]
+    )]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_detect_dialects(){
+    let dir = std::env::temp_dir().join("ccjson_detect");
+    let _ = std::fs::create_dir_all(&dir);
+    let log = dir.join("build.log");
+    std::fs::write(&log, "ninja: Entering directory `build'\n[1/2] cc -c ../a.c -o a.o\n[2/2] cc -c ../b.c -o b.o\n").unwrap();
+
+    let file = FileReader::new(&log.to_str().unwrap().to_string());
+    let mut parser: parser::Parser = parser::Parser::new(
+        Box::new(file), 
+        Some(String::from("/coder")),
+        false,
+    );
+    assert_eq!(parser.detect_dialects(2), ["ninja"]);
+    assert_eq!(parser.dialects(), ["ninja", "make"]);
+
+    let files: Vec<String> = parser.by_ref().flatten().map(|item| item.file).collect();
+    assert_eq!(files, ["a.c", "b.c"]);
+    let stats = parser.stats();
+    assert_eq!((stats.lines, stats.commands), (3, 2));
+    assert_eq!(stats.skipped.get("directory"), Some(&1));
+    assert_eq!(stats.compilers.iter().collect::<Vec<_>>(), ["cc"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
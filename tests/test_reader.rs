@@ -1,4 +1,6 @@
-use ccjson::reader::{FileReader, Reader};
+use std::{fs::{self, OpenOptions}, io::Write, thread, time::Duration};
+
+use ccjson::reader::{CommandReader, FileReader, FollowReader, KbuildReader, Reader};
 
 #[should_panic]
 #[test]
@@ -14,6 +16,7 @@ fn test_read_line(){
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_readable(){
     let mut file = FileReader::new(&String::from("./tests/hello.txt"));
     assert_eq!(true, file.readable());
@@ -22,4 +25,105 @@ fn test_readable(){
     assert_eq!(true, file.readable());
     file.read_line();
     assert_eq!(false, file.readable());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_command_reader(){
+    let command: Vec<String> = ["sh", "-c", "echo hello, ccjson!!!; exit 3"]
+        .iter().map(|s| s.to_string()).collect();
+    let mut build = CommandReader::new(&command, None).unwrap();
+    assert_eq!(build.read_line().unwrap(), "hello, ccjson!!!\n");
+    assert!(build.readable());
+    assert_eq!(build.read_line(), None);
+    assert!(!build.readable());
+    assert_eq!(build.exit_code(), Some(3));
+    assert!(build.echoes());
+    assert!(!FileReader::new(&String::from("./tests/hello.txt")).echoes());
+}
+
+#[test]
+fn test_command_reader_run_error(){
+    let command = vec!["/nonexistent/make".to_string()];
+    let error = CommandReader::new(&command, None).err().unwrap();
+    assert!(error.starts_with("can't run /nonexistent/make: "), "{}", error);
+}
+
+#[test]
+fn test_follow_reader(){
+    let dir = std::env::temp_dir().join("ccjson_follow");
+    let _ = fs::create_dir_all(&dir);
+    let log = dir.join("build.log");
+    fs::write(&log, "gcc -c a.c\n").unwrap();
+
+    let path = log.clone();
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"gcc -c ").unwrap();
+        file.flush().unwrap();
+        thread::sleep(Duration::from_millis(300));
+        file.write_all(b"b.c\n").unwrap();
+        // 日志轮转
+        fs::rename(&path, path.with_extension("old")).unwrap();
+        fs::write(&path, "gcc -c c.c\nDONE\nignored\n").unwrap();
+    });
+
+    let until = regex::Regex::new("^DONE").unwrap();
+    let mut file = FollowReader::new(log.to_str().unwrap(), Duration::from_secs(5), Some(until));
+    assert_eq!(file.read_line().unwrap(), "gcc -c a.c\n");
+    assert_eq!(file.read_line().unwrap(), "gcc -c b.c\n");
+    assert_eq!(file.read_line().unwrap(), "gcc -c c.c\n");
+    assert_eq!(file.read_line().unwrap(), "DONE\n");
+    assert!(!file.readable());
+    assert_eq!(file.read_line(), None);
+    writer.join().unwrap();
+
+    let mut file = FollowReader::new(log.to_str().unwrap(), Duration::from_millis(100), None);
+    while file.read_line().is_some() {}
+    assert!(!file.readable());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_follow_reader_rotated_partial(){
+    let dir = std::env::temp_dir().join("ccjson_follow_partial");
+    let _ = fs::create_dir_all(&dir);
+    let log = dir.join("build.log");
+    fs::write(&log, "gcc -c a.c\n").unwrap();
+
+    let path = log.clone();
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"gcc -c b.c").unwrap();
+        file.flush().unwrap();
+        thread::sleep(Duration::from_millis(300));
+        // 旧文件最后一行没有换行符
+        fs::rename(&path, path.with_extension("old")).unwrap();
+        fs::write(&path, "gcc -c c.c\n").unwrap();
+    });
+
+    let mut file = FollowReader::new(log.to_str().unwrap(), Duration::from_secs(1), None);
+    assert_eq!(file.read_line().unwrap(), "gcc -c a.c\n");
+    assert_eq!(file.read_line().unwrap(), "gcc -c b.c");
+    assert_eq!(file.read_line().unwrap(), "gcc -c c.c\n");
+    writer.join().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_kbuild_reader(){
+    let dir = std::env::temp_dir().join("ccjson_kbuild");
+    let _ = fs::create_dir_all(dir.join("drivers"));
+    fs::write(
+        dir.join("drivers/.foo.o.cmd"),
+        "savedcmd_drivers/foo.o := gcc -D\"S(s)=\\#s\" -c -o drivers/foo.o drivers/foo.c\n\nsource_drivers/foo.o := drivers/foo.c\n"
+    ).unwrap();
+    fs::write(dir.join("drivers/foo.o"), "").unwrap();
+
+    let mut tree = KbuildReader::new(dir.to_str().unwrap());
+    assert_eq!(tree.read_line().unwrap(), "savedcmd_drivers/foo.o := gcc -D\"S(s)=#s\" -c -o drivers/foo.o drivers/foo.c\n");
+    assert_eq!(tree.read_line(), None);
+    assert!(!tree.readable());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,41 @@
+#![cfg(target_os = "linux")]
+
+use ccjson::{reader::{Reader, Record}, tracer::TraceReader};
+
+#[test]
+fn test_trace_exec(){
+    let command: Vec<String> = ["sh", "-c", "true; exit 3"]
+        .iter().map(|s| s.to_string()).collect();
+    let mut build = TraceReader::new(&command).unwrap();
+
+    match build.read_record() {
+        Some(Record::Exec { directory, arguments }) => {
+            assert_eq!(arguments, command);
+            assert_eq!(directory, std::env::current_dir().unwrap().to_str().unwrap());
+        }
+        other => panic!("unexpected record: {:?}", other),
+    }
+
+    while build.read_record().is_some() {}
+    assert!(!build.readable());
+    assert_eq!(build.exit_code(), Some(3));
+}
+
+#[test]
+fn test_trace_empty_arguments(){
+    let command: Vec<String> = ["sh", "-c", "true; exit 0", "", "-DX="]
+        .iter().map(|s| s.to_string()).collect();
+    let mut build = TraceReader::new(&command).unwrap();
+    match build.read_record() {
+        Some(Record::Exec { arguments, .. }) => assert_eq!(arguments, command),
+        other => panic!("unexpected record: {:?}", other),
+    }
+    while build.read_record().is_some() {}
+}
+
+#[test]
+fn test_trace_run_error(){
+    let command = vec!["/nonexistent/make".to_string()];
+    let error = TraceReader::new(&command).err().unwrap();
+    assert!(error.starts_with("can't run /nonexistent/make: "), "{}", error);
+}
@@ -1,9 +1,10 @@
 use std::{fs::{self, File}, io::Read};
 
-use ccjson::writer::Writer;
+use ccjson::writer::{Clangd, Writer};
 
 
 #[test]
+#[allow(clippy::unnecessary_to_owned)]
 fn test_write_content(){
     let mut writer = Writer::new(Some(&"./tests/".to_string()), 1);
     let src_string = "hello, writer!!!";
@@ -18,4 +19,51 @@ fn test_write_content(){
     assert_eq!(buffer_size, src_size);
     let _ = fs::remove_file("./tests/compile_commands.json");
     assert_eq!(buffer, src_string.to_string());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_write_incremental(){
+    let dir = std::env::temp_dir().join("ccjson_incremental");
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("compile_commands.json");
+
+    let mut writer = Writer::new(dir.to_str(), 256);
+    writer.set_incremental(true);
+    writer.write("[\n");
+    writer.write("1");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "[\n1\n]");
+    writer.write("2");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "[\n1,\n2\n]");
+    writer.write("\n]");
+    writer.flush();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "[\n1,\n2\n]");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_write_clangd(){
+    let dir = std::env::temp_dir().join("ccjson_clangd");
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join(".clangd");
+    fs::write(&path, "If:\n  PathMatch: .*\\.h\nCompileFlags:\n  Add: [-xc]\n").unwrap();
+
+    let writer = Writer::new(dir.to_str(), 256);
+    let mut clangd = Clangd {
+        query_drivers: vec!["/opt/gcc/bin/arm-none-eabi-*".to_string()],
+        remove: vec!["-mlongcalls".to_string(), "-fno-tree-*".to_string()],
+        suppress: vec![],
+    };
+    writer.write_clangd(&clangd);
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.starts_with("If:\n  PathMatch: .*\\.h\nCompileFlags:\n  Add: [-xc]\n---\n# >>> ccjson\n"));
+    assert!(content.contains("  Remove: [\"-mlongcalls\", \"-fno-tree-*\"]\n"));
+
+    // 再次生成时只替换 ccjson 生成的部分
+    clangd.suppress.push("unknown-warning-option".to_string());
+    writer.write_clangd(&clangd);
+    let merged = fs::read_to_string(&path).unwrap();
+    assert_eq!(merged.matches("# >>> ccjson").count(), 1);
+    assert!(merged.starts_with("If:\n  PathMatch: .*\\.h\nCompileFlags:\n  Add: [-xc]\n---\n"));
+    assert!(merged.ends_with("Diagnostics:\n  Suppress: [\"unknown-warning-option\"]\n# <<< ccjson\n"));
+    let _ = fs::remove_dir_all(&dir);
+}